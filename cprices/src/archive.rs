@@ -0,0 +1,308 @@
+use chrono::Utc;
+use clap::{Arg, ArgMatches, Command};
+use cprices::data::{DBSaver, KLine};
+use cprices::{saver_start, Config};
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::fs;
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+
+use crate::binance::{to_kline, BinanceKLine};
+use crate::{binance, binance_futures, SAVE_BATCH_SIZE, SAVE_BATCH_WAIT};
+
+// open times above it are in microseconds, spot archives use them since 2025
+const MICROS_FROM: i64 = 100_000_000_000_000;
+
+pub fn command() -> Command {
+    Command::new("archive")
+        .about("Imports klines from data.binance.vision zip archives in a local directory")
+        .arg(
+            Arg::new("dir")
+                .long("dir")
+                .value_name("DIR")
+                .help("Directory with monthly or daily kline zips and their .CHECKSUM files, searched recursively")
+                .env("ARCHIVE_DIR")
+                .required(true),
+        )
+}
+
+pub async fn run_command(
+    db: Box<dyn DBSaver + Send + Sync>,
+    config: &Config,
+    args: &ArgMatches,
+) -> Result<(), Box<dyn Error>> {
+    if config.market != binance::MARKET && config.market != binance_futures::MARKET {
+        return Err(format!("no archives for market '{}'", config.market).into());
+    }
+    let dir = args.get_one::<String>("dir").ok_or("no dir")?;
+    let files = find_files(Path::new(dir))?;
+
+    let (tx, mut rx) = tokio::sync::mpsc::channel(SAVE_BATCH_SIZE);
+    let saver =
+        tokio::spawn(
+            async move { saver_start(db, &mut rx, SAVE_BATCH_SIZE, SAVE_BATCH_WAIT).await },
+        );
+    'pairs: for pair in &config.pairs {
+        let archives = select(&files, pair, &config.interval.to_string());
+        if archives.is_empty() {
+            log::warn!("no archives for {} {} in {}", pair, config.interval, dir);
+        }
+        for path in archives {
+            let (int_path, pair, interval, market) = (
+                path.clone(),
+                pair.clone(),
+                config.interval.to_string(),
+                config.market.clone(),
+            );
+            let lines = tokio::task::spawn_blocking(move || {
+                read_archive(&int_path, &pair, &interval, &market)
+            })
+            .await??;
+            log::info!("{}: {} lines", path.display(), lines.len());
+            for line in lines {
+                // the saver has failed, its error is returned below
+                if tx.send(line).await.is_err() {
+                    break 'pairs;
+                }
+            }
+        }
+    }
+    drop(tx);
+    saver.await??;
+    Ok(())
+}
+
+fn find_files(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let mut res = Vec::new();
+    let entries = fs::read_dir(dir).map_err(|e| format!("read dir {}: {}", dir.display(), e))?;
+    for entry in entries {
+        let path = entry
+            .map_err(|e| format!("read dir {}: {}", dir.display(), e))?
+            .path();
+        if path.is_dir() {
+            res.extend(find_files(&path)?);
+        } else if path.extension().is_some_and(|e| e == "zip") {
+            res.push(path);
+        }
+    }
+    Ok(res)
+}
+
+/// archives of the pair and interval named as BTCUSDT-1m-2023-01.zip or BTCUSDT-1m-2023-01-01.zip,
+/// ordered by date, a month goes before its days
+fn select(files: &[PathBuf], pair: &str, interval: &str) -> Vec<PathBuf> {
+    let prefix = format!("{}-{}-", pair, interval);
+    let mut res: Vec<(String, PathBuf)> = files
+        .iter()
+        .filter_map(|f| {
+            let name = f.file_name()?.to_str()?;
+            let date = name.strip_prefix(&prefix)?.strip_suffix(".zip")?;
+            let valid = matches!(date.len(), 7 | 10)
+                && date.chars().all(|c| c.is_ascii_digit() || c == '-');
+            valid.then(|| (date.to_string(), f.clone()))
+        })
+        .collect();
+    res.sort();
+    res.dedup_by(|a, b| a.0 == b.0);
+    res.into_iter().map(|(_, f)| f).collect()
+}
+
+fn read_archive(
+    path: &Path,
+    pair: &str,
+    interval: &str,
+    market: &str,
+) -> Result<Vec<KLine>, String> {
+    let data = fs::read(path).map_err(|e| format!("read {}: {}", path.display(), e))?;
+    let mut checksum_path = path.as_os_str().to_owned();
+    checksum_path.push(".CHECKSUM");
+    let checksum = fs::read_to_string(&checksum_path)
+        .map_err(|e| format!("read {}.CHECKSUM: {}", path.display(), e))?;
+    verify_checksum(&data, &checksum).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    let mut zip = zip::ZipArchive::new(Cursor::new(data))
+        .map_err(|e| format!("open {}: {}", path.display(), e))?;
+    let mut res = Vec::new();
+    for i in 0..zip.len() {
+        let mut file = zip
+            .by_index(i)
+            .map_err(|e| format!("open {}: {}", path.display(), e))?;
+        if !file.name().ends_with(".csv") {
+            continue;
+        }
+        let mut content = Vec::new();
+        file.read_to_end(&mut content)
+            .map_err(|e| format!("read {} {}: {}", path.display(), file.name(), e))?;
+        res.extend(
+            parse_csv(&content, pair, interval, market)
+                .map_err(|e| format!("{} {}: {}", path.display(), file.name(), e))?,
+        );
+    }
+    Ok(res)
+}
+
+// the .CHECKSUM file is the `sha256sum` output: <hex>  <file name>
+fn verify_checksum(data: &[u8], checksum: &str) -> Result<(), String> {
+    let expected = checksum
+        .split_whitespace()
+        .next()
+        .ok_or("empty checksum file")?;
+    let actual = format!("{:x}", Sha256::digest(data));
+    if !actual.eq_ignore_ascii_case(expected) {
+        return Err(format!(
+            "checksum mismatch, expected {}, got {}",
+            expected, actual
+        ));
+    }
+    Ok(())
+}
+
+// columns are the same as in the klines API, newer files have a header
+fn parse_csv(
+    content: &[u8],
+    pair: &str,
+    interval: &str,
+    market: &str,
+) -> Result<Vec<KLine>, String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .from_reader(content);
+    let now = Utc::now().timestamp_millis();
+    let mut res = Vec::new();
+    for (i, record) in reader.records().enumerate() {
+        let record = record.map_err(|e| format!("line {}: {}", i + 1, e))?;
+        if i == 0 && record.get(0).is_some_and(|f| f.parse::<i64>().is_err()) {
+            continue;
+        }
+        let mut d: BinanceKLine = record
+            .deserialize(None)
+            .map_err(|e| format!("line {}: {}", i + 1, e))?;
+        if d.open_time >= MICROS_FROM {
+            d.open_time /= 1000;
+            d.close_time /= 1000;
+        }
+        res.push(to_kline(&d, pair, interval, market, now));
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+    use std::io::Write;
+
+    const CSV: &str = "1672531200000,16541.77,16545.70,16508.39,16529.67,4364.83570,1672534799999,72146804.3,122544,2131.33,35228964.8,0\n\
+                       1672534800000,16529.59,16556.80,16525.78,16551.47,3590.06669,1672538399999,59386616.5,103986,1784.47,29522302.7,0\n";
+
+    fn zip(name: &str, content: &str) -> Vec<u8> {
+        let mut w = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        w.start_file(name, zip::write::FileOptions::default())
+            .unwrap();
+        w.write_all(content.as_bytes()).unwrap();
+        w.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn parses_csv() {
+        let res = parse_csv(CSV.as_bytes(), "BTCUSDT", "1h", "spot").unwrap();
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0].open_time, 1672531200000);
+        assert_eq!(res[0].close_time, 1672534799999);
+        assert_relative_eq!(res[0].open_price, 16541.77);
+        assert_relative_eq!(res[0].high_price, 16545.70);
+        assert_relative_eq!(res[0].low_price, 16508.39);
+        assert_relative_eq!(res[0].close_price, 16529.67);
+        assert_relative_eq!(res[0].volume, 4364.8357);
+        assert_relative_eq!(res[0].quote_volume, 72146804.3);
+        assert_eq!(res[0].trades, 122544);
+        assert_relative_eq!(res[1].taker_buy_volume, 1784.47);
+        assert_relative_eq!(res[1].taker_buy_quote_volume, 29522302.7);
+        assert_eq!(res[1].pair, "BTCUSDT");
+        assert_eq!(res[1].interval, "1h");
+        assert_eq!(res[1].market, "spot");
+        assert!(res.iter().all(|l| l.is_closed));
+    }
+
+    #[test]
+    fn skips_header() {
+        let content = format!(
+            "open_time,open,high,low,close,volume,close_time,quote_volume,count,taker_buy_volume,taker_buy_quote_volume,ignore\n{}",
+            CSV
+        );
+        let res = parse_csv(content.as_bytes(), "BTCUSDT", "1h", "usdm").unwrap();
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0].market, "usdm");
+    }
+
+    #[test]
+    fn converts_micros() {
+        let content = "1735689600000000,1,2,0.5,1.5,10,1735693199999999,15,3,5,7.5,0\n";
+        let res = parse_csv(content.as_bytes(), "BTCUSDT", "1h", "spot").unwrap();
+        assert_eq!(res[0].open_time, 1735689600000);
+        assert_eq!(res[0].close_time, 1735693199999);
+    }
+
+    #[test]
+    fn fails_on_wrong_line() {
+        let content = format!("{}1672538400000,x,1,1,1,1,1672541999999,1,1,1,1,0\n", CSV);
+        let err = parse_csv(content.as_bytes(), "BTCUSDT", "1h", "spot").unwrap_err();
+        assert!(err.contains("line 3"), "{}", err);
+    }
+
+    #[test]
+    fn verifies_checksum() {
+        let data = b"olia";
+        let sum = format!("{:x}", Sha256::digest(data));
+        assert!(verify_checksum(data, &format!("{}  BTCUSDT-1h-2023-01.zip\n", sum)).is_ok());
+        assert!(verify_checksum(data, &sum.to_uppercase()).is_ok());
+        assert!(verify_checksum(b"olia2", &sum).is_err());
+        assert!(verify_checksum(data, "").is_err());
+    }
+
+    #[test]
+    fn selects_archives() {
+        let files: Vec<PathBuf> = [
+            "d/BTCUSDT-1h-2023-02-01.zip",
+            "m/BTCUSDT-1h-2023-01.zip",
+            "d/BTCUSDT-1h-2023-01-31.zip",
+            "m/BTCUSDT-1m-2023-01.zip",
+            "m/ETHUSDT-1h-2023-01.zip",
+            "m/BTCUSDT-1h-2023-01.zip",
+            "BTCUSDT-1h-latest.zip",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+        let res = select(&files, "BTCUSDT", "1h");
+        assert_eq!(
+            res,
+            vec![
+                PathBuf::from("m/BTCUSDT-1h-2023-01.zip"),
+                PathBuf::from("d/BTCUSDT-1h-2023-01-31.zip"),
+                PathBuf::from("d/BTCUSDT-1h-2023-02-01.zip"),
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_archive() {
+        let dir = std::env::temp_dir().join(format!("cprices-archive-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("BTCUSDT-1h-2023-01-01.zip");
+        let data = zip("BTCUSDT-1h-2023-01-01.csv", CSV);
+        fs::write(&path, &data).unwrap();
+        let sum = format!("{:x}  BTCUSDT-1h-2023-01-01.zip", Sha256::digest(&data));
+        fs::write(dir.join("BTCUSDT-1h-2023-01-01.zip.CHECKSUM"), sum).unwrap();
+
+        assert_eq!(find_files(&dir).unwrap(), vec![path.clone()]);
+        let res = read_archive(&path, "BTCUSDT", "1h", "spot").unwrap();
+        assert_eq!(res.len(), 2);
+
+        fs::write(dir.join("BTCUSDT-1h-2023-01-01.zip.CHECKSUM"), "00  x").unwrap();
+        let err = read_archive(&path, "BTCUSDT", "1h", "spot").unwrap_err();
+        assert!(err.contains("checksum mismatch"), "{}", err);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
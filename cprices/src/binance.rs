@@ -1,75 +1,208 @@
 use async_trait::async_trait;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, TimeZone, Utc};
+use cprices::clock;
 use cprices::data::{KLine, Loader};
-use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
-use reqwest_retry::{policies::ExponentialBackoff, RetryTransientMiddleware};
+use cprices::interval::Interval;
+use cprices::LimiterM;
+use reqwest_middleware::ClientWithMiddleware;
+use serde::de::DeserializeOwned;
 use serde::de::{self, Deserializer, Unexpected, Visitor};
 use serde::{Deserialize, Serialize};
 use std::error::Error;
-use std::time::Duration;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::discovery::symbol_status;
+use crate::endpoints::{http_client, Endpoints};
+
+pub const MARKET: &str = "spot";
+/// request weight allowed per minute for an IP
+pub const WEIGHT_PER_MINUTE: u32 = 6000;
+const KLINES_WEIGHT: u32 = 2;
+const INFO_WEIGHT: u32 = 20;
+const USED_WEIGHT_HEADER: &str = "x-mbx-used-weight-1m";
+pub(crate) const URLS: &[&str] = &[
+    "https://api.binance.com",
+    "https://api1.binance.com",
+    "https://api2.binance.com",
+    "https://api3.binance.com",
+    "https://api4.binance.com",
+];
 
 #[derive(Debug)]
 pub struct Binance {
-    url: String,
+    endpoints: Endpoints,
     client: ClientWithMiddleware,
+    used_weight: AtomicU32,
 }
 
 impl Binance {
-    pub fn new() -> Result<Binance, Box<dyn Error>> {
-        let client = reqwest::Client::builder()
-            .connect_timeout(Duration::from_secs(5))
-            .timeout(Duration::from_secs(15))
-            .build()?;
-        let retry_policy = ExponentialBackoff::builder().build_with_max_retries(5);
-        let client = ClientBuilder::new(client)
-            .with(RetryTransientMiddleware::new_with_policy(retry_policy))
-            .build();
-
+    /// `urls` are tried in order, api[1-4].binance.com if empty
+    pub fn new(urls: &[String]) -> Result<Binance, Box<dyn Error>> {
         Ok(Binance {
-            url: "https://api.binance.com".to_string(),
-            client,
+            endpoints: Endpoints::new(urls, URLS)?,
+            client: http_client()?,
+            used_weight: AtomicU32::new(0),
         })
     }
 }
 
+pub(crate) async fn ping(
+    client: &ClientWithMiddleware,
+    endpoints: &Endpoints,
+    path: &str,
+) -> Result<String, Box<dyn Error>> {
+    let content = endpoints.get(client, path).await?.text().await?;
+    log::debug!("{} response: {}", endpoints.url(), content);
+    Ok(content)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ServerTime {
+    server_time: i64,
+}
+
+pub(crate) async fn server_time(
+    client: &ClientWithMiddleware,
+    endpoints: &Endpoints,
+    path: &str,
+) -> Result<Option<DateTime<Utc>>, Box<dyn Error>> {
+    let resp = endpoints
+        .get(client, path)
+        .await?
+        .json::<ServerTime>()
+        .await?;
+    Ok(Utc.timestamp_millis_opt(resp.server_time).single())
+}
+
+/// loads klines and keeps the used weight reported in the response
+pub(crate) async fn get_klines(
+    client: &ClientWithMiddleware,
+    endpoints: &Endpoints,
+    path: &str,
+    used_weight: &AtomicU32,
+) -> Result<Vec<BinanceKLine>, Box<dyn Error>> {
+    get_json(client, endpoints, path, used_weight).await
+}
+
+/// loads a response and keeps the used weight reported in it
+pub(crate) async fn get_json<T: DeserializeOwned>(
+    client: &ClientWithMiddleware,
+    endpoints: &Endpoints,
+    path: &str,
+    used_weight: &AtomicU32,
+) -> Result<T, Box<dyn Error>> {
+    let resp = endpoints.get(client, path).await?;
+    if let Some(used) = parse_used_weight(resp.headers()) {
+        used_weight.store(used, Ordering::Relaxed);
+    }
+    Ok(resp.json::<T>().await?)
+}
+
+/// loads a response once the limiter allows the request weight,
+/// the used weight reported in the response is passed to the limiter
+pub(crate) async fn get_limited<T: DeserializeOwned>(
+    client: &ClientWithMiddleware,
+    endpoints: &Endpoints,
+    path: &str,
+    weight: u32,
+    limiter: &LimiterM,
+) -> Result<T, Box<dyn Error>> {
+    limiter.lock().await.wait(weight).await?;
+    let resp = endpoints.get(client, path).await?;
+    if let Some(used) = parse_used_weight(resp.headers()) {
+        limiter.lock().await.update(used);
+    }
+    Ok(resp.json::<T>().await?)
+}
+
+fn parse_used_weight(headers: &reqwest::header::HeaderMap) -> Option<u32> {
+    headers.get(USED_WEIGHT_HEADER)?.to_str().ok()?.parse().ok()
+}
+
+/// takes the kept used weight, 0 means not reported
+pub(crate) fn take_used_weight(used_weight: &AtomicU32) -> Option<u32> {
+    match used_weight.swap(0, Ordering::Relaxed) {
+        0 => None,
+        v => Some(v),
+    }
+}
+
 #[async_trait]
 impl Loader for Binance {
     async fn live(&self) -> std::result::Result<String, Box<dyn Error>> {
-        let url = format!("{}/{}", self.url, "api/v3/ping");
-        log::debug!("Calling... {} ", url);
-        let content = self.client.get(url).send().await?.text().await?;
-        log::debug!("response: {}", content);
-        Ok(content)
+        ping(&self.client, &self.endpoints, "api/v3/ping").await
+    }
+    async fn server_time(&self) -> std::result::Result<Option<DateTime<Utc>>, Box<dyn Error>> {
+        server_time(&self.client, &self.endpoints, "api/v3/time").await
     }
     async fn retrieve(
         &self,
         pair: &str,
-        interval: &str,
+        interval: Interval,
         from: DateTime<Utc>,
     ) -> std::result::Result<Vec<KLine>, Box<dyn Error>> {
-        let url = format!(
-            "{}/{}?symbol={}&interval={}&startTime={}&limit={}",
-            self.url,
+        let interval = interval.binance()?;
+        let path = format!(
+            "{}?symbol={}&interval={}&startTime={}&limit={}",
             "api/v3/klines",
             pair,
             interval,
             from.timestamp_millis(),
             100
         );
-        log::debug!("Calling... {} ", url);
-        let resp = self
-            .client
-            .get(url)
-            .send()
-            .await?
-            .json::<Vec<BinanceKLine>>()
-            .await?;
-        let res = resp.iter().map(|d| to_kline(d, pair)).collect();
+        let resp = get_klines(&self.client, &self.endpoints, &path, &self.used_weight).await?;
+        let now = clock::now().timestamp_millis();
+        let res = resp
+            .iter()
+            .map(|d| to_kline(d, pair, &interval, MARKET, now))
+            .collect();
         Ok(res)
     }
+    async fn first_time(
+        &self,
+        pair: &str,
+        interval: Interval,
+    ) -> std::result::Result<Option<DateTime<Utc>>, Box<dyn Error>> {
+        let interval = interval.binance()?;
+        let path = format!(
+            "{}?symbol={}&interval={}&startTime=0&limit=1",
+            "api/v3/klines", pair, interval
+        );
+        let resp = get_klines(&self.client, &self.endpoints, &path, &self.used_weight).await?;
+        Ok(resp
+            .first()
+            .map(|d| Utc.timestamp_millis_opt(d.open_time).unwrap()))
+    }
+    async fn status(&self, pair: &str) -> std::result::Result<Option<String>, Box<dyn Error>> {
+        let path = format!("api/v3/exchangeInfo?symbol={}", pair);
+        symbol_status(
+            &self.endpoints,
+            &self.client,
+            &path,
+            pair,
+            &self.used_weight,
+        )
+        .await
+    }
+    fn weight(&self) -> u32 {
+        KLINES_WEIGHT
+    }
+    fn status_weight(&self) -> u32 {
+        INFO_WEIGHT
+    }
+    fn used_weight(&self) -> Option<u32> {
+        take_used_weight(&self.used_weight)
+    }
 }
 
-fn to_kline(d: &BinanceKLine, pair: &str) -> KLine {
+pub(crate) fn to_kline(
+    d: &BinanceKLine,
+    pair: &str,
+    interval: &str,
+    market: &str,
+    now: i64,
+) -> KLine {
     KLine {
         open_time: d.open_time,
         open_price: d.open_price,
@@ -78,12 +211,19 @@ fn to_kline(d: &BinanceKLine, pair: &str) -> KLine {
         close_price: d.close_price,
         volume: d.volume,
         close_time: d.close_time,
+        quote_volume: d.quote_volume,
+        trades: d.trades,
+        taker_buy_volume: d.taker_buy_base_volume,
+        taker_buy_quote_volume: d.taker_buy_quote_volume,
         pair: pair.to_string(),
+        interval: interval.to_string(),
+        market: market.to_string(),
+        is_closed: d.close_time < now,
     }
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
-struct BinanceKLine {
+pub(crate) struct BinanceKLine {
     // 1499040000000,      // Open time
     // "0.01634790",       // Open
     // "0.80000000",       // High
@@ -109,17 +249,17 @@ struct BinanceKLine {
     pub volume: f64,
     pub close_time: i64,
     #[serde(deserialize_with = "string_as_f64")]
-    pub asset_volume: f64,
+    pub quote_volume: f64,
     pub trades: i64,
     #[serde(deserialize_with = "string_as_f64")]
-    pub base_asset_volume: f64,
+    pub taker_buy_base_volume: f64,
     #[serde(deserialize_with = "string_as_f64")]
-    pub quote_asset_volume: f64,
+    pub taker_buy_quote_volume: f64,
     #[serde(deserialize_with = "string_as_f64")]
     pub other: f64,
 }
 
-fn string_as_f64<'de, D>(deserializer: D) -> Result<f64, D::Error>
+pub(crate) fn string_as_f64<'de, D>(deserializer: D) -> Result<f64, D::Error>
 where
     D: Deserializer<'de>,
 {
@@ -149,7 +289,36 @@ impl<'de> Visitor<'de> for F64Visitor {
 mod tests {
     use approx::assert_relative_eq;
 
-    use crate::binance::{to_kline, BinanceKLine};
+    use crate::binance::{
+        get_limited, parse_used_weight, take_used_weight, to_kline, BinanceKLine,
+    };
+    use crate::endpoints::Endpoints;
+    use async_trait::async_trait;
+    use cprices::data::Limiter;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Response, Server};
+    use reqwest::header::{HeaderMap, HeaderValue};
+    use std::convert::Infallible;
+    use std::error::Error;
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    // records waited and reported weights
+    struct TestLimiter {
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl Limiter for TestLimiter {
+        async fn wait(&self, weight: u32) -> Result<bool, Box<dyn Error>> {
+            self.calls.lock().unwrap().push(format!("wait {}", weight));
+            Ok(true)
+        }
+        fn update(&self, used: u32) {
+            self.calls.lock().unwrap().push(format!("used {}", used));
+        }
+    }
 
     fn one_sample() -> &'static str {
         r#"[1502942400000,
@@ -194,9 +363,71 @@ mod tests {
     #[test]
     fn mapt_to_kline() {
         let deserialized: BinanceKLine = serde_json::from_str(one_sample()).unwrap();
-        let kline = to_kline(&deserialized, "olia");
+        let kline = to_kline(&deserialized, "olia", "1h", "spot", 1502946000000);
         assert_eq!(kline.open_time, 1502942400000);
         assert_eq!(kline.close_time, 1502945999999);
         assert_eq!(kline.pair, "olia");
+        assert_eq!(kline.interval, "1h");
+        assert_eq!(kline.market, "spot");
+        assert_relative_eq!(kline.quote_volume, 202366.13839304);
+        assert_eq!(kline.trades, 171);
+        assert_relative_eq!(kline.taker_buy_volume, 35.160503);
+        assert_relative_eq!(kline.taker_buy_quote_volume, 150952.47794304);
+        assert!(kline.is_closed);
+    }
+    #[test]
+    fn mapt_to_open_kline() {
+        let deserialized: BinanceKLine = serde_json::from_str(one_sample()).unwrap();
+        let kline = to_kline(&deserialized, "olia", "1h", "spot", 1502945999999);
+        assert!(!kline.is_closed);
+    }
+
+    #[test]
+    fn parses_used_weight() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(parse_used_weight(&headers), None);
+        headers.insert("X-MBX-USED-WEIGHT-1M", HeaderValue::from_static("1234"));
+        assert_eq!(parse_used_weight(&headers), Some(1234));
+        headers.insert("X-MBX-USED-WEIGHT-1M", HeaderValue::from_static("olia"));
+        assert_eq!(parse_used_weight(&headers), None);
+    }
+
+    #[tokio::test]
+    async fn limits_by_weight() {
+        let make_svc = make_service_fn(|_conn| async {
+            Ok::<_, Infallible>(service_fn(|_req| async {
+                Ok::<_, Infallible>(
+                    Response::builder()
+                        .header("X-MBX-USED-WEIGHT-1M", "120")
+                        .body(Body::from("[1, 2]"))
+                        .unwrap(),
+                )
+            }))
+        });
+        let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_svc);
+        let url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let limiter: Box<dyn Limiter> = Box::new(TestLimiter {
+            calls: calls.clone(),
+        });
+        let limiter = Arc::new(tokio::sync::Mutex::new(limiter));
+        let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new()).build();
+        let endpoints = Endpoints::new(&[url], &[]).unwrap();
+        let res: Vec<i64> = get_limited(&client, &endpoints, "api/v3/exchangeInfo", 20, &limiter)
+            .await
+            .unwrap();
+        assert_eq!(res, vec![1, 2]);
+        assert_eq!(*calls.lock().unwrap(), vec!["wait 20", "used 120"]);
+    }
+
+    #[test]
+    fn takes_used_weight_once() {
+        let used = AtomicU32::new(0);
+        assert_eq!(take_used_weight(&used), None);
+        used.store(20, Ordering::Relaxed);
+        assert_eq!(take_used_weight(&used), Some(20));
+        assert_eq!(take_used_weight(&used), None);
     }
 }
@@ -0,0 +1,361 @@
+use async_trait::async_trait;
+use chrono::{DateTime, TimeZone, Utc};
+use cprices::clock;
+use cprices::data::{KLine, Loader};
+use cprices::interval::Interval;
+use reqwest_middleware::ClientWithMiddleware;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::str::FromStr;
+use std::sync::atomic::AtomicU32;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+use crate::binance::{get_json, get_klines, ping, server_time, take_used_weight, to_kline};
+use crate::endpoints::{http_client, Endpoints};
+
+pub const MARKET_PERPETUAL: &str = "coinm_perp";
+pub const MARKET_CURRENT_QUARTER: &str = "coinm_cq";
+pub const MARKET_NEXT_QUARTER: &str = "coinm_nq";
+/// request weight allowed per minute for an IP
+pub const WEIGHT_PER_MINUTE: u32 = 2400;
+// the weight is 2 for a limit in [100, 500), 5 in [500, 1000]
+const KLINES_LIMIT: u32 = 499;
+const KLINES_WEIGHT: u32 = 2;
+const INFO_WEIGHT: u32 = 1;
+// how long the loaded exchangeInfo is used to look up contracts
+const INFO_TTL: Duration = Duration::from_secs(600);
+const URLS: &[&str] = &["https://dapi.binance.com"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContractType {
+    Perpetual,
+    CurrentQuarter,
+    NextQuarter,
+}
+
+impl ContractType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContractType::Perpetual => "PERPETUAL",
+            ContractType::CurrentQuarter => "CURRENT_QUARTER",
+            ContractType::NextQuarter => "NEXT_QUARTER",
+        }
+    }
+
+    pub fn market(&self) -> &'static str {
+        match self {
+            ContractType::Perpetual => MARKET_PERPETUAL,
+            ContractType::CurrentQuarter => MARKET_CURRENT_QUARTER,
+            ContractType::NextQuarter => MARKET_NEXT_QUARTER,
+        }
+    }
+
+    pub fn from_market(market: &str) -> Option<ContractType> {
+        [
+            ContractType::Perpetual,
+            ContractType::CurrentQuarter,
+            ContractType::NextQuarter,
+        ]
+        .into_iter()
+        .find(|c| c.market() == market)
+    }
+}
+
+impl FromStr for ContractType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "PERPETUAL" => Ok(ContractType::Perpetual),
+            "CURRENT_QUARTER" => Ok(ContractType::CurrentQuarter),
+            "NEXT_QUARTER" => Ok(ContractType::NextQuarter),
+            _ => Err(format!("wrong contract type '{}'", s)),
+        }
+    }
+}
+
+/// Delivery contract traded in [onboard, delivery)
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Contract {
+    symbol: String,
+    onboard: i64,
+    delivery: i64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExchangeInfo {
+    symbols: Vec<ExchangeSymbol>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExchangeSymbol {
+    symbol: String,
+    pair: String,
+    contract_type: String,
+    delivery_date: i64,
+    onboard_date: i64,
+}
+
+/// Loads COIN-M futures klines of a pair (e.g. BTCUSD) for a contract type.
+/// Quarterly contracts are rolled over to the next one at delivery
+pub struct BinanceCoinM {
+    endpoints: Endpoints,
+    client: ClientWithMiddleware,
+    used_weight: AtomicU32,
+    contract_type: ContractType,
+    contracts: Mutex<HashMap<String, Contract>>,
+    /// exchangeInfo symbols and the time they were loaded, contracts are looked up in them
+    info: std::sync::Mutex<Option<(Instant, Arc<Vec<ExchangeSymbol>>)>>,
+}
+
+impl BinanceCoinM {
+    pub fn new(
+        urls: &[String],
+        contract_type: ContractType,
+    ) -> Result<BinanceCoinM, Box<dyn Error>> {
+        Ok(BinanceCoinM {
+            endpoints: Endpoints::new(urls, URLS)?,
+            client: http_client()?,
+            used_weight: AtomicU32::new(0),
+            contract_type,
+            contracts: Mutex::new(HashMap::new()),
+            info: std::sync::Mutex::new(None),
+        })
+    }
+
+    /// finds the contract traded at the time, None if it is not listed anymore
+    async fn contract(&self, pair: &str, at: i64) -> Result<Option<Contract>, Box<dyn Error>> {
+        let symbols = self.symbols().await?;
+        let res = select_contract(&symbols, pair, self.contract_type, at);
+        let mut contracts = self.contracts.lock().await;
+        match &res {
+            Some(c) if contracts.get(pair) != Some(c) => {
+                log::info!("{} {}: use {}", pair, self.contract_type.as_str(), c.symbol);
+                contracts.insert(pair.to_string(), c.clone());
+            }
+            Some(_) => {}
+            None => {
+                contracts.remove(pair);
+            }
+        }
+        Ok(res)
+    }
+
+    // exchangeInfo symbols, loaded again after the TTL
+    async fn symbols(&self) -> Result<Arc<Vec<ExchangeSymbol>>, Box<dyn Error>> {
+        if let Some((at, symbols)) = self.info.lock().unwrap().as_ref() {
+            if at.elapsed() < INFO_TTL {
+                return Ok(symbols.clone());
+            }
+        }
+        let info: ExchangeInfo = get_json(
+            &self.client,
+            &self.endpoints,
+            "dapi/v1/exchangeInfo",
+            &self.used_weight,
+        )
+        .await?;
+        let symbols = Arc::new(info.symbols);
+        *self.info.lock().unwrap() = Some((Instant::now(), symbols.clone()));
+        Ok(symbols)
+    }
+
+    fn info_expired(&self) -> bool {
+        match self.info.lock().unwrap().as_ref() {
+            Some((at, _)) => at.elapsed() >= INFO_TTL,
+            None => true,
+        }
+    }
+}
+
+// contract types in exchangeInfo are valid for now only, so quarters are ordered by delivery
+fn select_contract(
+    symbols: &[ExchangeSymbol],
+    pair: &str,
+    contract_type: ContractType,
+    at: i64,
+) -> Option<Contract> {
+    let mut alive: Vec<&ExchangeSymbol> = symbols
+        .iter()
+        // delivering contracts have an empty contract type
+        .filter(|s| s.pair == pair && !s.contract_type.is_empty())
+        .filter(|s| {
+            (s.contract_type == ContractType::Perpetual.as_str())
+                == (contract_type == ContractType::Perpetual)
+        })
+        .filter(|s| s.onboard_date <= at && at < s.delivery_date)
+        .collect();
+    alive.sort_by_key(|s| s.delivery_date);
+    let index = match contract_type {
+        ContractType::NextQuarter => 1,
+        _ => 0,
+    };
+    alive.get(index).map(|s| Contract {
+        symbol: s.symbol.clone(),
+        onboard: s.onboard_date,
+        delivery: s.delivery_date,
+    })
+}
+
+#[async_trait]
+impl Loader for BinanceCoinM {
+    async fn live(&self) -> std::result::Result<String, Box<dyn Error>> {
+        ping(&self.client, &self.endpoints, "dapi/v1/ping").await
+    }
+    async fn server_time(&self) -> std::result::Result<Option<DateTime<Utc>>, Box<dyn Error>> {
+        server_time(&self.client, &self.endpoints, "dapi/v1/time").await
+    }
+    async fn retrieve(
+        &self,
+        pair: &str,
+        interval: Interval,
+        from: DateTime<Utc>,
+    ) -> std::result::Result<Vec<KLine>, Box<dyn Error>> {
+        let interval = interval.binance()?;
+        let from = from.timestamp_millis();
+        let contract = self.contract(pair, from).await?;
+        let path = match &contract {
+            Some(c) => format!(
+                "{}?symbol={}&interval={}&startTime={}&limit={}",
+                "dapi/v1/klines", c.symbol, interval, from, KLINES_LIMIT
+            ),
+            // delivered contracts are not listed, take the history from the continuous series
+            None => format!(
+                "{}?pair={}&contractType={}&interval={}&startTime={}&limit={}",
+                "dapi/v1/continuousKlines",
+                pair,
+                self.contract_type.as_str(),
+                interval,
+                from,
+                KLINES_LIMIT
+            ),
+        };
+        let resp = get_klines(&self.client, &self.endpoints, &path, &self.used_weight).await?;
+        let delivery = contract.map_or(i64::MAX, |c| c.delivery);
+        let now = clock::now().timestamp_millis();
+        Ok(resp
+            .iter()
+            .filter(|d| d.open_time < delivery)
+            .map(|d| to_kline(d, pair, &interval, self.contract_type.market(), now))
+            .collect())
+    }
+    async fn first_time(
+        &self,
+        pair: &str,
+        interval: Interval,
+    ) -> std::result::Result<Option<DateTime<Utc>>, Box<dyn Error>> {
+        let interval = interval.binance()?;
+        let path = format!(
+            "{}?pair={}&contractType={}&interval={}&startTime=0&limit=1",
+            "dapi/v1/continuousKlines",
+            pair,
+            self.contract_type.as_str(),
+            interval
+        );
+        let resp = get_klines(&self.client, &self.endpoints, &path, &self.used_weight).await?;
+        Ok(resp
+            .first()
+            .map(|d| Utc.timestamp_millis_opt(d.open_time).unwrap()))
+    }
+    fn weight(&self) -> u32 {
+        match self.info_expired() {
+            true => KLINES_WEIGHT + INFO_WEIGHT,
+            false => KLINES_WEIGHT,
+        }
+    }
+    fn used_weight(&self) -> Option<u32> {
+        take_used_weight(&self.used_weight)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info() -> ExchangeInfo {
+        serde_json::from_str(
+            r#"{"timezone":"UTC","serverTime":1700000000000,"symbols":[
+            {"symbol":"BTCUSD_PERP","pair":"BTCUSD","contractType":"PERPETUAL","deliveryDate":4133404800000,
+             "onboardDate":1597042800000,"contractStatus":"TRADING","contractSize":100},
+            {"symbol":"BTCUSD_231229","pair":"BTCUSD","contractType":"CURRENT_QUARTER","deliveryDate":1703836800000,
+             "onboardDate":1688112000000,"contractStatus":"TRADING","contractSize":100},
+            {"symbol":"BTCUSD_240329","pair":"BTCUSD","contractType":"NEXT_QUARTER","deliveryDate":1711699200000,
+             "onboardDate":1695974400000,"contractStatus":"TRADING","contractSize":100},
+            {"symbol":"ETHUSD_PERP","pair":"ETHUSD","contractType":"PERPETUAL","deliveryDate":4133404800000,
+             "onboardDate":1597042800000,"contractStatus":"TRADING","contractSize":10}
+            ]}"#,
+        )
+        .unwrap()
+    }
+
+    fn select(contract_type: ContractType, at: i64) -> Option<String> {
+        select_contract(&info().symbols, "BTCUSD", contract_type, at).map(|c| c.symbol)
+    }
+
+    #[test]
+    fn parses_contract_type() {
+        assert_eq!(
+            "CURRENT_QUARTER".parse::<ContractType>().unwrap(),
+            ContractType::CurrentQuarter
+        );
+        assert!("QUARTER".parse::<ContractType>().is_err());
+        assert_eq!(
+            ContractType::from_market("coinm_nq"),
+            Some(ContractType::NextQuarter)
+        );
+        assert_eq!(ContractType::from_market("spot"), None);
+    }
+
+    #[test]
+    fn selects_contract() {
+        let oct = 1698000000000;
+        assert_eq!(select(ContractType::Perpetual, oct).unwrap(), "BTCUSD_PERP");
+        assert_eq!(
+            select(ContractType::CurrentQuarter, oct).unwrap(),
+            "BTCUSD_231229"
+        );
+        assert_eq!(
+            select(ContractType::NextQuarter, oct).unwrap(),
+            "BTCUSD_240329"
+        );
+    }
+
+    #[test]
+    fn rolls_over_at_delivery() {
+        let delivery = 1703836800000;
+        assert_eq!(
+            select(ContractType::CurrentQuarter, delivery - 1).unwrap(),
+            "BTCUSD_231229"
+        );
+        assert_eq!(
+            select(ContractType::CurrentQuarter, delivery).unwrap(),
+            "BTCUSD_240329"
+        );
+        assert_eq!(select(ContractType::NextQuarter, delivery), None);
+    }
+
+    #[test]
+    fn charges_info_weight_when_expired() {
+        let loader = BinanceCoinM::new(&[], ContractType::CurrentQuarter).unwrap();
+        assert_eq!(loader.weight(), KLINES_WEIGHT + INFO_WEIGHT);
+        *loader.info.lock().unwrap() = Some((Instant::now(), Arc::new(info().symbols)));
+        assert_eq!(loader.weight(), KLINES_WEIGHT);
+        let loaded = Instant::now() - INFO_TTL;
+        *loader.info.lock().unwrap() = Some((loaded, Arc::new(info().symbols)));
+        assert_eq!(loader.weight(), KLINES_WEIGHT + INFO_WEIGHT);
+    }
+
+    #[test]
+    fn no_contract_before_listing() {
+        assert_eq!(select(ContractType::CurrentQuarter, 1680000000000), None);
+        assert_eq!(
+            select(ContractType::Perpetual, 1680000000000).unwrap(),
+            "BTCUSD_PERP"
+        );
+    }
+}
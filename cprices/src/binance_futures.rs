@@ -0,0 +1,101 @@
+use async_trait::async_trait;
+use chrono::{DateTime, TimeZone, Utc};
+use cprices::clock;
+use cprices::data::{KLine, Loader};
+use cprices::interval::Interval;
+use reqwest_middleware::ClientWithMiddleware;
+use std::error::Error;
+use std::sync::atomic::AtomicU32;
+
+use crate::binance::{get_klines, ping, server_time, take_used_weight, to_kline};
+use crate::discovery::symbol_status;
+use crate::endpoints::{http_client, Endpoints};
+
+pub const MARKET: &str = "usdm";
+/// request weight allowed per minute for an IP
+pub const WEIGHT_PER_MINUTE: u32 = 2400;
+// the weight is 2 for a limit in [100, 500), 5 in [500, 1000]
+const KLINES_LIMIT: u32 = 499;
+const KLINES_WEIGHT: u32 = 2;
+pub(crate) const URLS: &[&str] = &["https://fapi.binance.com"];
+
+/// Loads USDⓈ-M futures klines
+#[derive(Debug)]
+pub struct BinanceFutures {
+    endpoints: Endpoints,
+    client: ClientWithMiddleware,
+    used_weight: AtomicU32,
+}
+
+impl BinanceFutures {
+    pub fn new(urls: &[String]) -> Result<BinanceFutures, Box<dyn Error>> {
+        Ok(BinanceFutures {
+            endpoints: Endpoints::new(urls, URLS)?,
+            client: http_client()?,
+            used_weight: AtomicU32::new(0),
+        })
+    }
+}
+
+#[async_trait]
+impl Loader for BinanceFutures {
+    async fn live(&self) -> std::result::Result<String, Box<dyn Error>> {
+        ping(&self.client, &self.endpoints, "fapi/v1/ping").await
+    }
+    async fn server_time(&self) -> std::result::Result<Option<DateTime<Utc>>, Box<dyn Error>> {
+        server_time(&self.client, &self.endpoints, "fapi/v1/time").await
+    }
+    async fn retrieve(
+        &self,
+        pair: &str,
+        interval: Interval,
+        from: DateTime<Utc>,
+    ) -> std::result::Result<Vec<KLine>, Box<dyn Error>> {
+        let interval = interval.binance()?;
+        let path = format!(
+            "{}?symbol={}&interval={}&startTime={}&limit={}",
+            "fapi/v1/klines",
+            pair,
+            interval,
+            from.timestamp_millis(),
+            KLINES_LIMIT
+        );
+        let resp = get_klines(&self.client, &self.endpoints, &path, &self.used_weight).await?;
+        let now = clock::now().timestamp_millis();
+        Ok(resp
+            .iter()
+            .map(|d| to_kline(d, pair, &interval, MARKET, now))
+            .collect())
+    }
+    async fn first_time(
+        &self,
+        pair: &str,
+        interval: Interval,
+    ) -> std::result::Result<Option<DateTime<Utc>>, Box<dyn Error>> {
+        let interval = interval.binance()?;
+        let path = format!(
+            "{}?symbol={}&interval={}&startTime=0&limit=1",
+            "fapi/v1/klines", pair, interval
+        );
+        let resp = get_klines(&self.client, &self.endpoints, &path, &self.used_weight).await?;
+        Ok(resp
+            .first()
+            .map(|d| Utc.timestamp_millis_opt(d.open_time).unwrap()))
+    }
+    async fn status(&self, pair: &str) -> std::result::Result<Option<String>, Box<dyn Error>> {
+        symbol_status(
+            &self.endpoints,
+            &self.client,
+            "fapi/v1/exchangeInfo",
+            pair,
+            &self.used_weight,
+        )
+        .await
+    }
+    fn weight(&self) -> u32 {
+        KLINES_WEIGHT
+    }
+    fn used_weight(&self) -> Option<u32> {
+        take_used_weight(&self.used_weight)
+    }
+}
@@ -0,0 +1,538 @@
+use chrono::{DateTime, Utc};
+use cprices::data::KLine;
+use cprices::{catch_up, fill_gaps, WorkingData};
+use futures::{SinkExt, StreamExt};
+use serde::Deserialize;
+use std::error::Error;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::binance::string_as_f64;
+use crate::{binance, binance_futures};
+
+// Binance drops a connection after 24h, reconnect a bit earlier
+const MAX_CONNECTION: Duration = Duration::from_secs(23 * 3600 + 50 * 60);
+// the server pings every 20s (3m for futures), no messages at all means a dead connection
+const READ_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+const MAX_RETRY_WAIT: Duration = Duration::from_secs(60);
+
+/// default stream URL of a market, None if the market has no stream
+pub fn default_url(market: &str) -> Option<&'static str> {
+    match market {
+        binance::MARKET => Some("wss://stream.binance.com:9443"),
+        binance_futures::MARKET => Some("wss://fstream.binance.com"),
+        _ => None,
+    }
+}
+
+/// Imports klines of all pairs from one combined `<symbol>@kline_<interval>` stream.
+/// Klines missed while reconnecting are loaded over REST
+pub struct BinanceStream {
+    url: String,
+    market: String,
+    max_connection: Duration,
+    read_timeout: Duration,
+    retry_wait: Duration,
+}
+
+enum Exit {
+    Closed,
+    Expired,
+}
+
+impl BinanceStream {
+    pub fn new(url: &str, market: &str) -> BinanceStream {
+        BinanceStream {
+            url: url.trim_end_matches('/').to_string(),
+            market: market.to_string(),
+            max_connection: MAX_CONNECTION,
+            read_timeout: READ_TIMEOUT,
+            retry_wait: Duration::from_secs(1),
+        }
+    }
+
+    pub async fn run_exit_indicator(
+        &self,
+        workers: Vec<WorkingData>,
+        close_ch: watch::Receiver<i32>,
+        exit_ind: tokio::sync::mpsc::UnboundedSender<i32>,
+    ) -> Result<(), Box<dyn Error>> {
+        if let Err(err) = self.run(workers, close_ch).await {
+            log::error!("{}", err);
+            log::info!("sending exit signal");
+            exit_ind.send(1)?;
+            return Err(err);
+        }
+        log::info!("exit stream");
+        Ok(())
+    }
+
+    pub async fn run(
+        &self,
+        workers: Vec<WorkingData>,
+        mut close_ch: watch::Receiver<i32>,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut last = Vec::new();
+        for w in &workers {
+            let mut state = PairState {
+                last: w.start_from,
+                behind: None,
+                gaps_left: !w.gaps.is_empty(),
+            };
+            state.catch_up(w).await;
+            last.push(state);
+        }
+        let mut wait = self.retry_wait;
+        loop {
+            let started = tokio::time::Instant::now();
+            match self.listen(&workers, &mut last, &mut close_ch).await {
+                Ok(Exit::Closed) => break,
+                Ok(Exit::Expired) => log::info!("reconnect before the 24h limit"),
+                Err(err) => {
+                    if started.elapsed() > MAX_RETRY_WAIT {
+                        wait = self.retry_wait;
+                    }
+                    log::warn!("stream: {}, reconnect in {:?}", err, wait);
+                    tokio::select! {
+                        _ = tokio::time::sleep(wait) => {},
+                        cr = close_ch.changed() => {
+                            if cr.is_err() {
+                                break;
+                            }
+                        }
+                    }
+                    wait = (wait * 2).min(MAX_RETRY_WAIT);
+                }
+            }
+            for (i, w) in workers.iter().enumerate() {
+                last[i].catch_up(w).await;
+            }
+        }
+        log::info!("exit stream loop");
+        Ok(())
+    }
+
+    async fn listen(
+        &self,
+        workers: &[WorkingData],
+        last: &mut [PairState],
+        close_ch: &mut watch::Receiver<i32>,
+    ) -> Result<Exit, Box<dyn Error>> {
+        let url = format!("{}/stream?streams={}", self.url, streams(workers));
+        let (mut ws, _) = tokio_tungstenite::connect_async(&url).await?;
+        log::info!("connected to {}", url);
+        let expire = tokio::time::sleep(self.max_connection);
+        tokio::pin!(expire);
+        loop {
+            let msg = tokio::select! {
+                _ = &mut expire => {
+                    let _ = ws.close(None).await;
+                    return Ok(Exit::Expired);
+                }
+                cr = close_ch.changed() => {
+                    if cr.is_err() {
+                        let _ = ws.close(None).await;
+                        return Ok(Exit::Closed);
+                    }
+                    continue;
+                }
+                msg = tokio::time::timeout(self.read_timeout, ws.next()) => {
+                    msg.map_err(|_| format!("no messages in {:?}", self.read_timeout))?
+                }
+            };
+            match msg.ok_or("stream ended")?? {
+                Message::Text(text) => {
+                    let line = match parse(&text, &self.market)? {
+                        Some(line) if line.is_closed => line,
+                        _ => continue,
+                    };
+                    let i = match workers.iter().position(|w| w.pair == line.pair) {
+                        Some(i) => i,
+                        None => continue,
+                    };
+                    log::debug!(
+                        "{} {}: closed kline {}",
+                        line.pair,
+                        line.interval,
+                        line.open_time()
+                    );
+                    last[i].last = last[i].last.max(line.open_time());
+                    workers[i].sender.send(line).await?;
+                }
+                // tungstenite queues the pong, send it now
+                Message::Ping(_) => ws.flush().await?,
+                Message::Close(frame) => {
+                    return Err(format!("closed by server: {:?}", frame).into())
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+// import state of a streamed pair
+struct PairState {
+    // the last imported or streamed open time
+    last: DateTime<Utc>,
+    // start of a failed catch up, retried on the next reconnect
+    behind: Option<DateTime<Utc>>,
+    gaps_left: bool,
+}
+
+impl PairState {
+    // fills the gaps and imports the missed klines, a failure is logged
+    // and does not stop the streams of other pairs
+    async fn catch_up(&mut self, w: &WorkingData) {
+        if self.gaps_left {
+            match fill_gaps(w, &w.gaps).await {
+                Ok(count) => {
+                    log::info!(
+                        "filled {} gaps of {} with {} lines",
+                        w.gaps.len(),
+                        w.pair,
+                        count
+                    );
+                    self.gaps_left = false;
+                }
+                Err(err) => log::error!("fill gaps of {}: {}, retry on reconnect", w.pair, err),
+            }
+        }
+        let from = self.behind.unwrap_or(self.last);
+        match catch_up(w, from).await {
+            Ok(last) => {
+                self.last = self.last.max(last);
+                self.behind = None;
+            }
+            Err(err) => {
+                log::error!("catch up {}: {}, retry on reconnect", w.pair, err);
+                self.behind = Some(from);
+            }
+        }
+    }
+}
+
+fn streams(workers: &[WorkingData]) -> String {
+    workers
+        .iter()
+        .map(|w| format!("{}@kline_{}", w.pair.to_lowercase(), w.interval))
+        .collect::<Vec<String>>()
+        .join("/")
+}
+
+/// parses a combined stream message, None if it is not a kline
+fn parse(text: &str, market: &str) -> Result<Option<KLine>, String> {
+    let msg: StreamMessage = serde_json::from_str(text)
+        .map_err(|e| format!("wrong stream message '{}': {}", text, e))?;
+    let k = match msg.data {
+        Some(StreamData { k: Some(k) }) => k,
+        _ => return Ok(None),
+    };
+    Ok(Some(KLine {
+        open_time: k.open_time,
+        open_price: k.open_price,
+        high_price: k.high_price,
+        low_price: k.low_price,
+        close_price: k.close_price,
+        volume: k.volume,
+        close_time: k.close_time,
+        quote_volume: k.quote_volume,
+        trades: k.trades,
+        taker_buy_volume: k.taker_buy_base_volume,
+        taker_buy_quote_volume: k.taker_buy_quote_volume,
+        pair: k.symbol,
+        interval: k.interval,
+        market: market.to_string(),
+        is_closed: k.is_closed,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamMessage {
+    data: Option<StreamData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamData {
+    k: Option<StreamKLine>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamKLine {
+    #[serde(rename = "t")]
+    open_time: i64,
+    #[serde(rename = "T")]
+    close_time: i64,
+    #[serde(rename = "s")]
+    symbol: String,
+    #[serde(rename = "i")]
+    interval: String,
+    #[serde(rename = "o", deserialize_with = "string_as_f64")]
+    open_price: f64,
+    #[serde(rename = "c", deserialize_with = "string_as_f64")]
+    close_price: f64,
+    #[serde(rename = "h", deserialize_with = "string_as_f64")]
+    high_price: f64,
+    #[serde(rename = "l", deserialize_with = "string_as_f64")]
+    low_price: f64,
+    #[serde(rename = "v", deserialize_with = "string_as_f64")]
+    volume: f64,
+    #[serde(rename = "n")]
+    trades: i64,
+    #[serde(rename = "x")]
+    is_closed: bool,
+    #[serde(rename = "q", deserialize_with = "string_as_f64")]
+    quote_volume: f64,
+    #[serde(rename = "V", deserialize_with = "string_as_f64")]
+    taker_buy_base_volume: f64,
+    #[serde(rename = "Q", deserialize_with = "string_as_f64")]
+    taker_buy_quote_volume: f64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use chrono::TimeZone;
+    use cprices::data::{Limiter, Loader};
+    use cprices::interval::Interval;
+    use std::sync::{Arc, Mutex};
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+
+    const T0: i64 = 1672531200000;
+
+    fn message(open_time: i64, closed: bool) -> String {
+        format!(
+            r#"{{"stream":"btcusdt@kline_1m","data":{{"e":"kline","E":{},"s":"BTCUSDT","k":{{"t":{},"T":{},"s":"BTCUSDT","i":"1m","f":100,"L":200,"o":"16541.77","c":"16542.10","h":"16545.70","l":"16508.39","v":"43.6","n":101,"x":{},"q":"721468.04","V":"21.3","Q":"352289.64","B":"0"}}}}}}"#,
+            open_time + 59999,
+            open_time,
+            open_time + 59999,
+            closed
+        )
+    }
+
+    struct TestLoader {
+        calls: Arc<Mutex<Vec<i64>>>,
+        // count of the first calls to fail
+        fail: usize,
+    }
+
+    #[async_trait]
+    impl Loader for TestLoader {
+        async fn live(&self) -> Result<String, Box<dyn Error>> {
+            Ok("{}".to_string())
+        }
+        async fn retrieve(
+            &self,
+            pair: &str,
+            interval: Interval,
+            from: DateTime<Utc>,
+        ) -> Result<Vec<KLine>, Box<dyn Error>> {
+            let from = from.timestamp_millis();
+            let calls = {
+                let mut calls = self.calls.lock().unwrap();
+                calls.push(from);
+                calls.len()
+            };
+            if calls <= self.fail {
+                return Err("exchange is down".into());
+            }
+            // the next kline only, the stream continues after it
+            if from != T0 {
+                return Ok(vec![]);
+            }
+            let mut line = parse(&message(T0 + 60000, true), "spot")?.unwrap();
+            line.pair = pair.to_string();
+            line.interval = interval.to_string();
+            Ok(vec![line])
+        }
+    }
+
+    struct TestLimiter {}
+
+    #[async_trait]
+    impl Limiter for TestLimiter {
+        async fn wait(&self, _weight: u32) -> Result<bool, Box<dyn Error>> {
+            Ok(true)
+        }
+    }
+
+    #[test]
+    fn parses_kline() {
+        let line = parse(&message(T0, true), "usdm").unwrap().unwrap();
+        assert_eq!(line.open_time, T0);
+        assert_eq!(line.close_time, T0 + 59999);
+        assert_eq!(line.pair, "BTCUSDT");
+        assert_eq!(line.interval, "1m");
+        assert_eq!(line.market, "usdm");
+        assert_eq!(line.open_price, 16541.77);
+        assert_eq!(line.close_price, 16542.10);
+        assert_eq!(line.high_price, 16545.70);
+        assert_eq!(line.low_price, 16508.39);
+        assert_eq!(line.volume, 43.6);
+        assert_eq!(line.quote_volume, 721468.04);
+        assert_eq!(line.trades, 101);
+        assert_eq!(line.taker_buy_volume, 21.3);
+        assert_eq!(line.taker_buy_quote_volume, 352289.64);
+        assert!(line.is_closed);
+        assert!(
+            !parse(&message(T0, false), "spot")
+                .unwrap()
+                .unwrap()
+                .is_closed
+        );
+    }
+
+    #[test]
+    fn skips_other_messages() {
+        assert!(parse(r#"{"result":null,"id":1}"#, "spot")
+            .unwrap()
+            .is_none());
+        assert!(parse("olia", "spot").is_err());
+    }
+
+    // connection 1: ping, open and closed klines, then a drop
+    // connection 2: the next closed kline after the one loaded over REST
+    // the handshake callback signature is given by tungstenite
+    #[allow(clippy::result_large_err)]
+    async fn serve(paths: Arc<Mutex<Vec<String>>>, pongs: Arc<Mutex<Vec<Vec<u8>>>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            for conn in 0..2 {
+                let (stream, _) = listener.accept().await.unwrap();
+                let int_paths = paths.clone();
+                let mut ws = tokio_tungstenite::accept_hdr_async(
+                    stream,
+                    move |req: &Request, resp: Response| {
+                        int_paths.lock().unwrap().push(req.uri().to_string());
+                        Ok(resp)
+                    },
+                )
+                .await
+                .unwrap();
+                if conn == 0 {
+                    ws.send(Message::Ping(b"olia".to_vec())).await.unwrap();
+                    while let Some(Ok(msg)) = ws.next().await {
+                        if let Message::Pong(data) = msg {
+                            pongs.lock().unwrap().push(data);
+                            break;
+                        }
+                    }
+                    ws.send(Message::Text(message(T0, false))).await.unwrap();
+                    ws.send(Message::Text(message(T0, true))).await.unwrap();
+                    drop(ws);
+                } else {
+                    ws.send(Message::Text(message(T0 + 120000, false)))
+                        .await
+                        .unwrap();
+                    ws.send(Message::Text(message(T0 + 120000, true)))
+                        .await
+                        .unwrap();
+                    // keep open till the client closes
+                    while let Some(Ok(_)) = ws.next().await {}
+                }
+            }
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn streams_and_fills_after_reconnect() {
+        let paths = Arc::new(Mutex::new(Vec::new()));
+        let pongs = Arc::new(Mutex::new(Vec::new()));
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let url = serve(paths.clone(), pongs.clone()).await;
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        let limiter: Box<dyn Limiter> = Box::new(TestLimiter {});
+        let workers = vec![WorkingData {
+            pair: "BTCUSDT".to_string(),
+            market: "spot".to_string(),
+            interval: "1m".parse().unwrap(),
+            start_from: Utc.timestamp_millis_opt(T0 - 60000).unwrap(),
+            gaps: vec![],
+            loader: Box::new(TestLoader {
+                calls: calls.clone(),
+                fail: 0,
+            }),
+            limiter: Arc::new(tokio::sync::Mutex::new(limiter)),
+            sender: tx,
+            status_db: None,
+        }];
+        let mut stream = BinanceStream::new(&url, "spot");
+        stream.retry_wait = Duration::from_millis(10);
+        let (tx_close, rx_close) = watch::channel(0);
+
+        let check = async move {
+            let mut res = Vec::new();
+            while res.len() < 3 {
+                let line: KLine = rx.recv().await.unwrap();
+                assert!(line.is_closed);
+                res.push(line.open_time);
+            }
+            drop(tx_close);
+            res
+        };
+        let (run, res) = tokio::time::timeout(Duration::from_secs(10), async {
+            tokio::join!(stream.run(workers, rx_close), check)
+        })
+        .await
+        .unwrap();
+        run.unwrap();
+        assert_eq!(res, vec![T0, T0 + 60000, T0 + 120000]);
+        assert_eq!(calls.lock().unwrap()[..3], [T0 - 60000, T0, T0 + 60000]);
+        assert_eq!(*pongs.lock().unwrap(), vec![b"olia".to_vec()]);
+        let paths = paths.lock().unwrap();
+        assert_eq!(paths.len(), 2);
+        assert_eq!(paths[0], "/stream?streams=btcusdt@kline_1m");
+    }
+
+    #[tokio::test]
+    async fn retries_failed_catch_up_on_reconnect() {
+        let paths = Arc::new(Mutex::new(Vec::new()));
+        let pongs = Arc::new(Mutex::new(Vec::new()));
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let url = serve(paths.clone(), pongs.clone()).await;
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        let limiter: Box<dyn Limiter> = Box::new(TestLimiter {});
+        let workers = vec![WorkingData {
+            pair: "BTCUSDT".to_string(),
+            market: "spot".to_string(),
+            interval: "1m".parse().unwrap(),
+            start_from: Utc.timestamp_millis_opt(T0 - 60000).unwrap(),
+            gaps: vec![],
+            loader: Box::new(TestLoader {
+                calls: calls.clone(),
+                fail: 1,
+            }),
+            limiter: Arc::new(tokio::sync::Mutex::new(limiter)),
+            sender: tx,
+            status_db: None,
+        }];
+        let mut stream = BinanceStream::new(&url, "spot");
+        stream.retry_wait = Duration::from_millis(10);
+        let (tx_close, rx_close) = watch::channel(0);
+
+        let check = async move {
+            let mut res = Vec::new();
+            while res.len() < 2 {
+                let line: KLine = rx.recv().await.unwrap();
+                res.push(line.open_time);
+            }
+            drop(tx_close);
+            res
+        };
+        let (run, res) = tokio::time::timeout(Duration::from_secs(10), async {
+            tokio::join!(stream.run(workers, rx_close), check)
+        })
+        .await
+        .unwrap();
+        run.unwrap();
+        assert_eq!(res, vec![T0, T0 + 120000]);
+        // the failed catch up is retried from its start after the reconnect
+        assert_eq!(calls.lock().unwrap()[..2], [T0 - 60000, T0 - 60000]);
+    }
+}
@@ -0,0 +1,41 @@
+use chrono::{DateTime, Duration, Utc};
+use std::sync::atomic::{AtomicI64, Ordering};
+
+// exchange clock minus the local clock, ms
+static OFFSET_MS: AtomicI64 = AtomicI64::new(0);
+
+/// exchange time estimated by the local clock corrected with the last measured offset
+pub fn now() -> DateTime<Utc> {
+    Utc::now() + offset()
+}
+
+pub fn offset() -> Duration {
+    Duration::milliseconds(OFFSET_MS.load(Ordering::Relaxed))
+}
+
+pub fn set_offset(offset: Duration) {
+    OFFSET_MS.store(offset.num_milliseconds(), Ordering::Relaxed);
+}
+
+/// offset of the server time taken between the local `before` and `after`,
+/// the server is assumed to answer in the middle of the round trip
+pub fn estimate(before: DateTime<Utc>, server: DateTime<Utc>, after: DateTime<Utc>) -> Duration {
+    server - (before + (after - before) / 2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn estimates_offset() {
+        let t = |ms: i64| Utc.timestamp_millis_opt(ms).unwrap();
+        assert_eq!(estimate(t(1000), t(1100), t(1200)), Duration::zero());
+        assert_eq!(estimate(t(1000), t(61100), t(1200)), Duration::seconds(60));
+        assert_eq!(
+            estimate(t(1000), t(500), t(1000)),
+            Duration::milliseconds(-500)
+        );
+    }
+}
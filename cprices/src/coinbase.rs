@@ -0,0 +1,189 @@
+use async_trait::async_trait;
+use chrono::{DateTime, SecondsFormat, Utc};
+use cprices::data::{KLine, Loader};
+use cprices::interval::Interval;
+use reqwest_middleware::ClientWithMiddleware;
+use serde::Deserialize;
+use std::error::Error;
+
+use crate::endpoints::{http_client, Endpoints};
+
+pub const MARKET: &str = "coinbase";
+/// allowed requests per minute, public endpoints allow 10 per second
+pub const REQUESTS_PER_MINUTE: u32 = 60;
+const URLS: &[&str] = &["https://api.exchange.coinbase.com"];
+const CANDLES_LIMIT: u32 = 300;
+
+/// Loads Coinbase Exchange candles, pairs as BTC-USD
+#[derive(Debug)]
+pub struct Coinbase {
+    endpoints: Endpoints,
+    client: ClientWithMiddleware,
+}
+
+impl Coinbase {
+    pub fn new(urls: &[String]) -> Result<Coinbase, Box<dyn Error>> {
+        Ok(Coinbase {
+            endpoints: Endpoints::new(urls, URLS)?,
+            client: http_client()?,
+        })
+    }
+}
+
+#[async_trait]
+impl Loader for Coinbase {
+    async fn live(&self) -> std::result::Result<String, Box<dyn Error>> {
+        let content = self
+            .endpoints
+            .get(&self.client, "time")
+            .await?
+            .text()
+            .await?;
+        log::debug!("{} response: {}", self.endpoints.url(), content);
+        Ok(content)
+    }
+    async fn retrieve(
+        &self,
+        pair: &str,
+        interval: Interval,
+        from: DateTime<Utc>,
+    ) -> std::result::Result<Vec<KLine>, Box<dyn Error>> {
+        let granularity = interval.coinbase()?;
+        // both bounds are inclusive
+        let end = interval.after(from, CANDLES_LIMIT - 1);
+        let path = format!(
+            "products/{}/candles?granularity={}&start={}&end={}",
+            pair,
+            granularity,
+            from.to_rfc3339_opts(SecondsFormat::Secs, true),
+            end.to_rfc3339_opts(SecondsFormat::Secs, true)
+        );
+        let resp = self
+            .endpoints
+            .get(&self.client, &path)
+            .await?
+            .json::<Vec<CoinbaseCandle>>()
+            .await?;
+        Ok(to_klines(
+            &resp,
+            pair,
+            &interval.to_string(),
+            granularity,
+            Utc::now().timestamp_millis(),
+        ))
+    }
+    fn next_window(&self, interval: Interval, from: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        Some(interval.after(from, CANDLES_LIMIT - 1))
+    }
+}
+
+// candles come newest first
+fn to_klines(
+    resp: &[CoinbaseCandle],
+    pair: &str,
+    interval: &str,
+    granularity: i64,
+    now: i64,
+) -> Vec<KLine> {
+    let mut res: Vec<KLine> = resp
+        .iter()
+        .map(|d| to_kline(d, pair, interval, granularity, now))
+        .collect();
+    res.sort_by_key(|l| l.open_time);
+    res
+}
+
+fn to_kline(d: &CoinbaseCandle, pair: &str, interval: &str, granularity: i64, now: i64) -> KLine {
+    let open_time = d.time * 1000;
+    let close_time = open_time + granularity * 1000 - 1;
+    KLine {
+        open_time,
+        open_price: d.open,
+        high_price: d.high,
+        low_price: d.low,
+        close_price: d.close,
+        volume: d.volume,
+        close_time,
+        // not provided by coinbase
+        quote_volume: 0.0,
+        trades: 0,
+        taker_buy_volume: 0.0,
+        taker_buy_quote_volume: 0.0,
+        pair: pair.to_string(),
+        interval: interval.to_string(),
+        market: MARKET.to_string(),
+        is_closed: close_time < now,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct CoinbaseCandle {
+    // [ time, low, high, open, close, volume ]
+    // time is the bucket start in unix seconds
+    pub time: i64,
+    pub low: f64,
+    pub high: f64,
+    pub open: f64,
+    pub close: f64,
+    pub volume: f64,
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+
+    use crate::coinbase::{to_klines, CoinbaseCandle};
+
+    // GET products/BTC-USD/candles?granularity=3600&start=2023-10-01T00:00:00Z&end=2023-10-01T02:00:00Z
+    fn sample() -> &'static str {
+        r#"[[1696122000,26950.01,27010.55,26985.43,27002.18,112.63210517],
+        [1696118400,26931.6,26998.2,26962.57,26985.43,154.4031297],
+        [1696114800,26902,26975,26967.27,26962.58,98.04316411]]"#
+    }
+
+    #[test]
+    fn deserialize_candles() {
+        let deserialized: Vec<CoinbaseCandle> = serde_json::from_str(sample()).unwrap();
+        assert_eq!(deserialized.len(), 3);
+        assert_eq!(deserialized[0].time, 1696122000);
+        assert_relative_eq!(deserialized[0].low, 26950.01);
+        assert_relative_eq!(deserialized[0].high, 27010.55);
+        assert_relative_eq!(deserialized[0].open, 26985.43);
+        assert_relative_eq!(deserialized[0].close, 27002.18);
+        assert_relative_eq!(deserialized[0].volume, 112.63210517);
+        assert_relative_eq!(deserialized[2].low, 26902.0);
+    }
+
+    #[test]
+    fn deserialize_empty() {
+        let deserialized: Vec<CoinbaseCandle> = serde_json::from_str("[]").unwrap();
+        assert!(deserialized.is_empty());
+    }
+
+    #[test]
+    fn maps_to_klines_ascending() {
+        let deserialized: Vec<CoinbaseCandle> = serde_json::from_str(sample()).unwrap();
+        let res = to_klines(&deserialized, "BTC-USD", "1h", 3600, 1696125600000);
+        assert_eq!(res.len(), 3);
+        assert_eq!(res[0].open_time, 1696114800000);
+        assert_eq!(res[1].open_time, 1696118400000);
+        assert_eq!(res[2].open_time, 1696122000000);
+        assert_eq!(res[2].close_time, 1696125599999);
+        assert_relative_eq!(res[2].open_price, 26985.43);
+        assert_relative_eq!(res[2].high_price, 27010.55);
+        assert_relative_eq!(res[2].low_price, 26950.01);
+        assert_relative_eq!(res[2].close_price, 27002.18);
+        assert_eq!(res[2].pair, "BTC-USD");
+        assert_eq!(res[2].interval, "1h");
+        assert_eq!(res[2].market, "coinbase");
+        assert!(res.iter().all(|l| l.is_closed));
+    }
+
+    #[test]
+    fn marks_open_candle() {
+        let deserialized: Vec<CoinbaseCandle> = serde_json::from_str(sample()).unwrap();
+        let res = to_klines(&deserialized, "BTC-USD", "1h", 3600, 1696122000000 + 10);
+        assert!(res[1].is_closed);
+        assert!(!res[2].is_closed);
+    }
+}
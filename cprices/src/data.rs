@@ -1,8 +1,10 @@
 use async_trait::async_trait;
-use chrono::{DateTime, TimeZone, Utc};
+use chrono::{DateTime, Duration, TimeZone, Utc};
 use serde::{Deserialize, Serialize};
 use std::error::Error;
 
+use crate::interval::Interval;
+
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct KLine {
     pub open_time: i64,
@@ -12,7 +14,16 @@ pub struct KLine {
     pub close_price: f64,
     pub volume: f64,
     pub close_time: i64,
+    pub quote_volume: f64,
+    pub trades: i64,
+    pub taker_buy_volume: f64,
+    pub taker_buy_quote_volume: f64,
     pub pair: String,
+    pub interval: String,
+    /// market of the exchange, e.g. spot, usdm, keeps same symbol series apart
+    pub market: String,
+    /// false for the still open candle, its values will change till close_time
+    pub is_closed: bool,
 }
 
 impl KLine {
@@ -23,52 +34,176 @@ impl KLine {
         )
     }
     pub fn open_time(&self) -> DateTime<Utc> {
-        Utc.timestamp(
-            self.open_time / 1000,
-            ((self.open_time % 1000) * 1000000).try_into().unwrap(),
-        )
+        Utc.timestamp_millis_opt(self.open_time).unwrap()
+    }
+}
+/// Missing klines with open time in [from, to)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Gap {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+}
+
+impl Gap {
+    pub fn missing(&self, interval: Interval) -> i64 {
+        interval.count(self.from, self.to)
     }
 }
+
 #[async_trait]
-pub trait Loader {
+pub trait Loader: Send + Sync {
     async fn live(&self) -> Result<String, Box<dyn Error>>;
     async fn retrieve(
         &self,
         pair: &str,
-        interval: &str,
+        interval: Interval,
         from: DateTime<Utc>,
     ) -> Result<Vec<KLine>, Box<dyn Error>>;
+    /// open time of the first available kline, None if the loader can not tell
+    async fn first_time(
+        &self,
+        _pair: &str,
+        _interval: Interval,
+    ) -> Result<Option<DateTime<Utc>>, Box<dyn Error>> {
+        Ok(None)
+    }
+    /// start of the next time window for loaders retrieving a window per call,
+    /// used to go on after a window without klines, e.g. before the listing.
+    /// None if the loader returns klines from `from` on
+    fn next_window(&self, _interval: Interval, _from: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        None
+    }
+    /// weight of a retrieve call in the exchange's rate limit
+    fn weight(&self) -> u32 {
+        1
+    }
+    /// weight of a status call in the exchange's rate limit
+    fn status_weight(&self) -> u32 {
+        1
+    }
+    /// weight used in the current minute as reported by the exchange in the last response,
+    /// None if not reported since the previous call
+    fn used_weight(&self) -> Option<u32> {
+        None
+    }
+    /// exchange server time, None if the loader can not tell
+    async fn server_time(&self) -> Result<Option<DateTime<Utc>>, Box<dyn Error>> {
+        Ok(None)
+    }
+    /// exchange status of the pair, e.g. TRADING or BREAK, None if the loader can not tell
+    async fn status(&self, _pair: &str) -> Result<Option<String>, Box<dyn Error>> {
+        Ok(None)
+    }
 }
 
 #[async_trait]
 pub trait DBSaver {
     async fn live(&self) -> Result<String, Box<dyn Error>>;
-    async fn get_last_time(&self, pair: &str) -> Result<DateTime<Utc>, Box<dyn Error>>;
+    async fn get_last_time(
+        &self,
+        market: &str,
+        pair: &str,
+        interval: &str,
+    ) -> Result<Option<DateTime<Utc>>, Box<dyn Error>>;
+    /// holes between stored klines, step is the interval duration
+    async fn get_gaps(
+        &self,
+        market: &str,
+        pair: &str,
+        interval: &str,
+        step: Duration,
+    ) -> Result<Vec<Gap>, Box<dyn Error>>;
     async fn save(&self, data: &KLine) -> Result<bool, Box<dyn Error>>;
+    async fn save_batch(&self, data: &[KLine]) -> Result<bool, Box<dyn Error>> {
+        for line in data {
+            self.save(line).await?;
+        }
+        Ok(true)
+    }
+    /// keeps klines failed the sanity checks aside from the prices, the default drops them
+    async fn quarantine(&self, _data: &[Rejected]) -> Result<bool, Box<dyn Error>> {
+        Ok(false)
+    }
+    /// records a change of the pair's exchange status, the default drops it
+    async fn save_status(&self, _data: &SymbolStatus) -> Result<bool, Box<dyn Error>> {
+        Ok(false)
+    }
+}
+
+/// exchange status of a pair open for trading
+pub const TRADING: &str = "TRADING";
+
+/// Exchange status of a pair since the time
+#[derive(Debug, Clone, PartialEq)]
+pub struct SymbolStatus {
+    pub time: DateTime<Utc>,
+    pub pair: String,
+    pub market: String,
+    pub status: String,
+}
+
+/// KLine failed the sanity checks
+#[derive(Debug, Clone)]
+pub struct Rejected {
+    pub line: KLine,
+    /// short rule name, used as a metric label
+    pub rule: &'static str,
+    pub reason: String,
 }
 
 #[async_trait]
-pub trait Limiter {
-    async fn wait(&self) -> Result<bool, Box<dyn Error>>;
+pub trait Limiter: Send + Sync {
+    /// waits until a request of the weight is allowed
+    async fn wait(&self, weight: u32) -> Result<bool, Box<dyn Error>>;
+    /// takes the weight used in the current minute as reported by the exchange
+    fn update(&self, _used: u32) {}
+}
+
+/// closed kline of the test pair with the close time matching the interval
+#[cfg(test)]
+pub(crate) fn test_kline(open_time: i64, interval: &str) -> KLine {
+    let open = Utc.timestamp_millis_opt(open_time).unwrap();
+    KLine {
+        open_time,
+        open_price: 1.0,
+        high_price: 2.0,
+        low_price: 0.1,
+        close_price: 1.5,
+        volume: 10.0,
+        close_time: interval
+            .parse::<Interval>()
+            .unwrap()
+            .close_time(open)
+            .timestamp_millis(),
+        quote_volume: 15.0,
+        trades: 3,
+        taker_buy_volume: 4.0,
+        taker_buy_quote_volume: 6.0,
+        pair: "olia".to_string(),
+        interval: interval.to_string(),
+        market: "spot".to_string(),
+        is_closed: true,
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::data::KLine;
+    use crate::data::{test_kline, Gap};
+    use chrono::{TimeZone, Utc};
+    #[test]
+    fn gap_missing() {
+        let gap = Gap {
+            from: Utc.timestamp_opt(3600, 0).unwrap(),
+            to: Utc.timestamp_opt(3 * 3600, 0).unwrap(),
+        };
+        assert_eq!(gap.missing("1h".parse().unwrap()), 2);
+        assert_eq!(gap.missing("15m".parse().unwrap()), 8);
+        assert_eq!(gap.missing("1d".parse().unwrap()), 1);
+    }
     #[test]
     fn to_string() {
         assert_eq!(
-            KLine {
-                open_time: 10,
-                open_price: 1.0,
-                high_price: 2.0,
-                low_price: 0.1,
-                close_price: 1.5,
-                volume: 10.0,
-                close_time: 15,
-                pair: "olia".to_string(),
-            }
-            .to_str(),
+            test_kline(10, "1h").to_str(),
             "pair: olia, time: 10, price 1"
         );
     }
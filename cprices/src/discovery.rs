@@ -0,0 +1,558 @@
+use clap::{Arg, ArgAction, ArgMatches};
+use futures::stream::FuturesUnordered;
+use futures::{Future, StreamExt};
+use reqwest_middleware::ClientWithMiddleware;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::sync::atomic::AtomicU32;
+use std::time::Duration;
+use tokio::sync::watch;
+
+use cprices::data::TRADING;
+use cprices::LimiterM;
+
+use crate::binance::{get_json, get_limited, string_as_f64};
+use crate::endpoints::{http_client, Endpoints};
+use crate::{binance, binance_futures};
+
+/// status of a pair missing in the exchangeInfo
+const DELISTED: &str = "DELISTED";
+
+pub fn args() -> Vec<Arg> {
+    vec![
+        Arg::new("discover")
+            .long("discover")
+            .help("Imports all TRADING symbols matching the discovery rules instead of --pair (spot and usdm)")
+            .env("DISCOVER")
+            .action(ArgAction::SetTrue),
+        Arg::new("quote")
+            .long("quote")
+            .value_name("ASSET")
+            .help("Discovers symbols of the quote asset, e.g. : USDT")
+            .env("QUOTE"),
+        Arg::new("include")
+            .long("include")
+            .value_name("GLOBS")
+            .help("Discovers symbols matching any glob separated by comma, e.g. : BTC*,ETH*")
+            .env("INCLUDE"),
+        Arg::new("exclude")
+            .long("exclude")
+            .value_name("GLOBS")
+            .help("Skips symbols matching any glob separated by comma, e.g. : *UPUSDT,*DOWNUSDT")
+            .env("EXCLUDE"),
+        Arg::new("min_quote_volume")
+            .long("min-quote-volume")
+            .value_name("VOLUME")
+            .help("Discovers symbols with the 24h quote volume not less than it")
+            .env("MIN_QUOTE_VOLUME")
+            .value_parser(clap::value_parser!(f64)),
+        Arg::new("discover_every")
+            .long("discover-every")
+            .value_name("DURATION")
+            .help("How often to look for new symbols")
+            .env("DISCOVER_EVERY")
+            .default_value("1h"),
+        Arg::new("invalid_pairs")
+            .long("invalid-pairs")
+            .value_name("ACTION")
+            .help("What to do with configured pairs that are unknown or not trading in the exchange")
+            .env("INVALID_PAIRS")
+            .value_parser(["fail", "skip"])
+            .default_value("fail"),
+    ]
+}
+
+// exchangeInfo and all symbols 24h ticker requests of a market
+struct InfoPaths {
+    defaults: &'static [&'static str],
+    info: &'static str,
+    info_weight: u32,
+    ticker: &'static str,
+    ticker_weight: u32,
+}
+
+fn info_paths(market: &str) -> Option<InfoPaths> {
+    match market {
+        binance::MARKET => Some(InfoPaths {
+            defaults: binance::URLS,
+            info: "api/v3/exchangeInfo",
+            info_weight: 20,
+            ticker: "api/v3/ticker/24hr",
+            ticker_weight: 80,
+        }),
+        binance_futures::MARKET => Some(InfoPaths {
+            defaults: binance_futures::URLS,
+            info: "fapi/v1/exchangeInfo",
+            info_weight: 1,
+            ticker: "fapi/v1/ticker/24hr",
+            ticker_weight: 40,
+        }),
+        _ => None,
+    }
+}
+
+/// status of the pair in the exchangeInfo, DELISTED if it is not listed
+/// or the exchange rejects the symbol
+pub(crate) async fn symbol_status(
+    endpoints: &Endpoints,
+    client: &ClientWithMiddleware,
+    path: &str,
+    pair: &str,
+    used_weight: &AtomicU32,
+) -> Result<Option<String>, Box<dyn Error>> {
+    match get_json::<ExchangeInfo>(client, endpoints, path, used_weight).await {
+        Ok(info) => Ok(Some(status(&info.symbols, pair))),
+        Err(err) if is_invalid_symbol(&err.to_string()) => Ok(Some(DELISTED.to_string())),
+        Err(err) => Err(err),
+    }
+}
+
+// binance answers 400 {"code":-1121,"msg":"Invalid symbol."} for an unknown symbol
+fn is_invalid_symbol(err: &str) -> bool {
+    err.contains(r#""code":-1121"#)
+}
+
+fn status(symbols: &[ExchangeSymbol], pair: &str) -> String {
+    symbols
+        .iter()
+        .find(|s| s.symbol == pair)
+        .map_or(DELISTED.to_string(), |s| s.status.clone())
+}
+
+/// checks the configured pairs against the exchangeInfo, returns the valid ones
+/// or fails if there are invalid pairs and they are not to be skipped
+pub async fn check_pairs(
+    args: &ArgMatches,
+    market: &str,
+    urls: &[String],
+    pairs: &[String],
+    limiter: &LimiterM,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let Some(paths) = info_paths(market) else {
+        log::debug!("no pairs check for market '{}'", market);
+        return Ok(pairs.to_vec());
+    };
+    let endpoints = Endpoints::new(urls, paths.defaults)?;
+    let info: ExchangeInfo = get_limited(
+        &http_client()?,
+        &endpoints,
+        paths.info,
+        paths.info_weight,
+        limiter,
+    )
+    .await?;
+    let problems = check(&info.symbols, pairs);
+    if problems.is_empty() {
+        return Ok(pairs.to_vec());
+    }
+    let skip = args
+        .get_one::<String>("invalid_pairs")
+        .is_some_and(|v| v == "skip");
+    for (_, problem) in problems.iter() {
+        match skip {
+            true => log::warn!("skip {}", problem),
+            false => log::error!("{}", problem),
+        }
+    }
+    if !skip {
+        return Err(format!(
+            "{} invalid pair(s), fix them or start with --invalid-pairs skip",
+            problems.len()
+        )
+        .into());
+    }
+    Ok(pairs
+        .iter()
+        .filter(|p| !problems.iter().any(|(bad, _)| bad == *p))
+        .cloned()
+        .collect())
+}
+
+// invalid pairs with the problem description
+fn check(symbols: &[ExchangeSymbol], pairs: &[String]) -> Vec<(String, String)> {
+    let by_name: HashMap<&str, &ExchangeSymbol> =
+        symbols.iter().map(|s| (s.symbol.as_str(), s)).collect();
+    pairs
+        .iter()
+        .filter_map(|pair| {
+            let problem = match by_name.get(pair.as_str()) {
+                Some(s) if s.status == TRADING => return None,
+                Some(s) => format!("pair '{}' is not trading, status {}", pair, s.status),
+                None => match suggest(symbols, pair).as_slice() {
+                    [] => format!("unknown pair '{}'", pair),
+                    similar => format!(
+                        "unknown pair '{}', did you mean {}?",
+                        pair,
+                        similar.join(", ")
+                    ),
+                },
+            };
+            Some((pair.clone(), problem))
+        })
+        .collect()
+}
+
+// up to 3 closest trading symbols
+fn suggest(symbols: &[ExchangeSymbol], pair: &str) -> Vec<String> {
+    const MAX_DISTANCE: usize = 2;
+    let pair = pair.to_uppercase();
+    let mut res: Vec<(usize, &str)> = symbols
+        .iter()
+        .filter(|s| s.status == TRADING)
+        .map(|s| (strsim::levenshtein(&pair, &s.symbol), s.symbol.as_str()))
+        .filter(|(d, _)| *d <= MAX_DISTANCE)
+        .collect();
+    res.sort();
+    res.into_iter()
+        .take(3)
+        .map(|(_, s)| s.to_string())
+        .collect()
+}
+
+/// Rules to select symbols
+#[derive(Debug, Clone, Default)]
+struct Rules {
+    quote: Option<String>,
+    include: Vec<String>,
+    exclude: Vec<String>,
+    min_quote_volume: Option<f64>,
+}
+
+impl Rules {
+    fn matches(&self, s: &ExchangeSymbol) -> bool {
+        s.status == TRADING
+            && self.quote.as_ref().map_or(true, |q| q == &s.quote_asset)
+            && (self.include.is_empty() || self.include.iter().any(|g| glob(g, &s.symbol)))
+            && !self.exclude.iter().any(|g| glob(g, &s.symbol))
+    }
+}
+
+/// Finds symbols to import in the exchangeInfo
+pub struct Discovery {
+    endpoints: Endpoints,
+    client: ClientWithMiddleware,
+    limiter: LimiterM,
+    paths: InfoPaths,
+    rules: Rules,
+    every: Duration,
+}
+
+impl Discovery {
+    /// None if the discovery is not enabled
+    pub fn new(
+        args: &ArgMatches,
+        market: &str,
+        urls: &[String],
+        limiter: LimiterM,
+    ) -> Result<Option<Discovery>, Box<dyn Error>> {
+        if !args.get_flag("discover") {
+            return Ok(None);
+        }
+        let paths = info_paths(market)
+            .ok_or_else(|| format!("no symbol discovery for market '{}'", market))?;
+        let globs = |name: &str| -> Vec<String> {
+            args.get_one::<String>(name)
+                .map(|v| {
+                    v.split(',')
+                        .map(|s| s.trim().to_uppercase())
+                        .filter(|s| !s.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        let rules = Rules {
+            quote: args
+                .get_one::<String>("quote")
+                .map(|s| s.trim().to_uppercase()),
+            include: globs("include"),
+            exclude: globs("exclude"),
+            min_quote_volume: args.get_one::<f64>("min_quote_volume").copied(),
+        };
+        let every = args
+            .get_one::<String>("discover_every")
+            .ok_or("no discover every")?;
+        let every = duration_str::parse(every)
+            .map_err(|e| format!("wrong discover every '{}': {}", every, e))?;
+        log::info!("Discover {:?}, every {:?}", rules, every);
+        Ok(Some(Discovery {
+            endpoints: Endpoints::new(urls, paths.defaults)?,
+            client: http_client()?,
+            limiter,
+            paths,
+            rules,
+            every,
+        }))
+    }
+
+    /// symbols matching the rules, sorted
+    pub async fn symbols(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        let (client, endpoints, limiter) = (&self.client, &self.endpoints, &self.limiter);
+        let paths = &self.paths;
+        let info: ExchangeInfo =
+            get_limited(client, endpoints, paths.info, paths.info_weight, limiter).await?;
+        let volumes: Option<Vec<Ticker>> = match self.rules.min_quote_volume {
+            Some(_) => Some(
+                get_limited(
+                    client,
+                    endpoints,
+                    paths.ticker,
+                    paths.ticker_weight,
+                    limiter,
+                )
+                .await?,
+            ),
+            None => None,
+        };
+        Ok(select(&info.symbols, volumes.as_deref(), &self.rules))
+    }
+
+    /// looks for new symbols periodically and runs a task for each
+    pub async fn watch<F, Fut>(
+        &self,
+        mut known: HashSet<String>,
+        new_task: F,
+        mut close_ch: watch::Receiver<i32>,
+    ) -> Result<(), Box<dyn Error>>
+    where
+        F: Fn(String) -> Fut,
+        Fut: Future<Output = Result<(), Box<dyn Error>>>,
+    {
+        let mut tasks = FuturesUnordered::new();
+        let mut ticker =
+            tokio::time::interval_at(tokio::time::Instant::now() + self.every, self.every);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {
+                    match self.symbols().await {
+                        Ok(symbols) => {
+                            for symbol in symbols {
+                                if known.insert(symbol.clone()) {
+                                    log::info!("New symbol {}", symbol);
+                                    tasks.push(new_task(symbol));
+                                }
+                            }
+                        }
+                        Err(err) => log::warn!("discover symbols: {}", err),
+                    }
+                }
+                Some(res) = tasks.next(), if !tasks.is_empty() => {
+                    if let Err(e) = res {
+                        log::error!("problem importing: {e}");
+                    }
+                }
+                _ = close_ch.changed() => break,
+            }
+        }
+        while let Some(res) = tasks.next().await {
+            if let Err(e) = res {
+                log::error!("problem importing: {e}");
+            }
+        }
+        Ok(())
+    }
+}
+
+fn select(symbols: &[ExchangeSymbol], tickers: Option<&[Ticker]>, rules: &Rules) -> Vec<String> {
+    let volumes: HashMap<&str, f64> = tickers
+        .unwrap_or_default()
+        .iter()
+        .map(|t| (t.symbol.as_str(), t.quote_volume))
+        .collect();
+    let mut res: Vec<String> = symbols
+        .iter()
+        .filter(|s| rules.matches(s))
+        .filter(|s| {
+            rules.min_quote_volume.map_or(true, |min| {
+                volumes.get(s.symbol.as_str()).is_some_and(|v| *v >= min)
+            })
+        })
+        .map(|s| s.symbol.clone())
+        .collect();
+    res.sort();
+    res
+}
+
+// `*` matches any text, `?` any char
+fn glob(pattern: &str, text: &str) -> bool {
+    let (p, t): (Vec<char>, Vec<char>) = (pattern.chars().collect(), text.chars().collect());
+    let (mut pi, mut ti) = (0, 0);
+    // position of the last `*` and the text position it matched up to
+    let mut star: Option<(usize, usize)> = None;
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ti));
+            pi += 1;
+        } else if let Some((sp, st)) = star {
+            pi = sp + 1;
+            ti = st + 1;
+            star = Some((sp, st + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|c| *c == '*')
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ExchangeInfo {
+    symbols: Vec<ExchangeSymbol>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExchangeSymbol {
+    symbol: String,
+    status: String,
+    quote_asset: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Ticker {
+    symbol: String,
+    #[serde(deserialize_with = "string_as_f64")]
+    quote_volume: f64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info() -> ExchangeInfo {
+        serde_json::from_str(
+            r#"{"timezone":"UTC","serverTime":1700000000000,"symbols":[
+            {"symbol":"BTCUSDT","status":"TRADING","baseAsset":"BTC","quoteAsset":"USDT"},
+            {"symbol":"ETHUSDT","status":"TRADING","baseAsset":"ETH","quoteAsset":"USDT"},
+            {"symbol":"ETHBTC","status":"TRADING","baseAsset":"ETH","quoteAsset":"BTC"},
+            {"symbol":"BTCUPUSDT","status":"TRADING","baseAsset":"BTCUP","quoteAsset":"USDT"},
+            {"symbol":"LUNAUSDT","status":"BREAK","baseAsset":"LUNA","quoteAsset":"USDT"},
+            {"symbol":"DOGEUSDT","status":"TRADING","baseAsset":"DOGE","quoteAsset":"USDT"},
+            {"symbol":"1000PEPEUSDT","status":"TRADING","baseAsset":"1000PEPE","quoteAsset":"USDT"}
+            ]}"#,
+        )
+        .unwrap()
+    }
+
+    fn tickers() -> Vec<Ticker> {
+        serde_json::from_str(
+            r#"[{"symbol":"BTCUSDT","priceChange":"1.0","quoteVolume":"1000000.5"},
+            {"symbol":"ETHUSDT","quoteVolume":"500000.0"},
+            {"symbol":"DOGEUSDT","quoteVolume":"99.9"}]"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn selects_by_quote() {
+        let rules = Rules {
+            quote: Some("USDT".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            select(&info().symbols, None, &rules),
+            vec![
+                "1000PEPEUSDT",
+                "BTCUPUSDT",
+                "BTCUSDT",
+                "DOGEUSDT",
+                "ETHUSDT"
+            ]
+        );
+    }
+
+    #[test]
+    fn selects_by_globs() {
+        let rules = Rules {
+            include: vec!["BTC*".to_string(), "ETH*".to_string()],
+            exclude: vec!["*UPUSDT".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            select(&info().symbols, None, &rules),
+            vec!["BTCUSDT", "ETHBTC", "ETHUSDT"]
+        );
+    }
+
+    #[test]
+    fn selects_by_volume() {
+        let rules = Rules {
+            quote: Some("USDT".to_string()),
+            min_quote_volume: Some(100.0),
+            ..Default::default()
+        };
+        assert_eq!(
+            select(&info().symbols, Some(&tickers()), &rules),
+            vec!["BTCUSDT", "ETHUSDT"]
+        );
+    }
+
+    #[test]
+    fn checks_pairs() {
+        let pairs = ["BTCUSDT", "BTCUSTD", "LUNAUSDT", "XYZ", "ethusdt"].map(String::from);
+        let res = check(&info().symbols, &pairs);
+        assert_eq!(
+            res,
+            vec![
+                (
+                    "BTCUSTD".to_string(),
+                    "unknown pair 'BTCUSTD', did you mean BTCUSDT?".to_string()
+                ),
+                (
+                    "LUNAUSDT".to_string(),
+                    "pair 'LUNAUSDT' is not trading, status BREAK".to_string()
+                ),
+                ("XYZ".to_string(), "unknown pair 'XYZ'".to_string()),
+                (
+                    "ethusdt".to_string(),
+                    "unknown pair 'ethusdt', did you mean ETHUSDT, BTCUSDT?".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_status() {
+        assert_eq!(status(&info().symbols, "BTCUSDT"), "TRADING");
+        assert_eq!(status(&info().symbols, "LUNAUSDT"), "BREAK");
+        assert_eq!(status(&info().symbols, "XYZ"), "DELISTED");
+    }
+
+    #[test]
+    fn detects_invalid_symbol() {
+        assert!(is_invalid_symbol(
+            r#"https://api.binance.com/api/v3/exchangeInfo?symbol=XYZ: 400 Bad Request: {"code":-1121,"msg":"Invalid symbol."}"#
+        ));
+        assert!(!is_invalid_symbol(
+            r#"https://api.binance.com/api/v3/exchangeInfo?symbol=XYZ: 429 Too Many Requests: {"code":-1003,"msg":"Too many requests."}"#
+        ));
+    }
+
+    #[test]
+    fn suggests_closest() {
+        assert_eq!(suggest(&info().symbols, "ETHUSD"), vec!["ETHUSDT"]);
+        assert_eq!(
+            suggest(&info().symbols, "BTCUPUSDT"),
+            vec!["BTCUPUSDT", "BTCUSDT"]
+        );
+        assert!(suggest(&info().symbols, "LUNAUSDT").is_empty());
+    }
+
+    #[test]
+    fn matches_glob() {
+        assert!(glob("*", "BTCUSDT"));
+        assert!(glob("BTC*", "BTCUSDT"));
+        assert!(glob("*USDT", "BTCUSDT"));
+        assert!(glob("B?C*T", "BTCUSDT"));
+        assert!(glob("*US*T", "BTCUSDT"));
+        assert!(glob("BTCUSDT", "BTCUSDT"));
+        assert!(!glob("BTC", "BTCUSDT"));
+        assert!(!glob("*BTC", "BTCUSDT"));
+        assert!(!glob("ETH*", "BTCUSDT"));
+        assert!(glob("", ""));
+        assert!(!glob("", "BTC"));
+    }
+}
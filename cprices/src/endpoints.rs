@@ -0,0 +1,367 @@
+use async_trait::async_trait;
+use once_cell::sync::Lazy;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{Request, Response, StatusCode};
+use reqwest_middleware::{ClientBuilder, ClientWithMiddleware, Middleware, Next};
+use reqwest_retry::{policies::ExponentialBackoff, RetryTransientMiddleware};
+use std::error::Error;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use task_local_extensions::Extensions;
+use tokio::time::Instant;
+
+// waits if the server does not tell how long
+const TOO_MANY_REQUESTS_WAIT: Duration = Duration::from_secs(60);
+const BANNED_WAIT: Duration = Duration::from_secs(5 * 60);
+
+// clients of the process share the IP and so the server's limits
+static IP_PAUSE: Lazy<Arc<Pause>> = Lazy::new(|| Arc::new(Pause::default()));
+
+/// HTTP client retrying transient errors, the calls are paused on 429 and 418
+pub fn http_client() -> Result<ClientWithMiddleware, Box<dyn Error>> {
+    let client = reqwest::Client::builder()
+        .connect_timeout(Duration::from_secs(5))
+        .timeout(Duration::from_secs(15))
+        .user_agent(concat!("cprices/", env!("CARGO_PKG_VERSION")))
+        .build()?;
+    let retry_policy = ExponentialBackoff::builder().build_with_max_retries(5);
+    Ok(ClientBuilder::new(client)
+        .with(RetryTransientMiddleware::new_with_policy(retry_policy))
+        .with(RetryAfterMiddleware {
+            pause: IP_PAUSE.clone(),
+        })
+        .build())
+}
+
+/// Time till calls to the server are not allowed
+#[derive(Debug, Default)]
+struct Pause {
+    until: Mutex<Option<Instant>>,
+}
+
+impl Pause {
+    /// extends the pause, false if it already lasts longer
+    fn set(&self, wait: Duration) -> bool {
+        let until = Instant::now() + wait;
+        let mut current = self.until.lock().unwrap();
+        if current.is_some_and(|c| c >= until) {
+            return false;
+        }
+        *current = Some(until);
+        true
+    }
+
+    async fn wait(&self) {
+        loop {
+            let until = *self.until.lock().unwrap();
+            match until {
+                Some(until) if until > Instant::now() => tokio::time::sleep_until(until).await,
+                _ => return,
+            }
+        }
+    }
+}
+
+/// Waits as the server asks on 429 (too many requests) or 418 (IP banned) and repeats the call.
+/// Goes after the retry middleware, so it never retries these statuses on its own schedule
+struct RetryAfterMiddleware {
+    pause: Arc<Pause>,
+}
+
+#[async_trait]
+impl Middleware for RetryAfterMiddleware {
+    async fn handle(
+        &self,
+        req: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> reqwest_middleware::Result<Response> {
+        loop {
+            self.pause.wait().await;
+            let attempt = match req.try_clone() {
+                Some(attempt) => attempt,
+                None => return next.run(req, extensions).await,
+            };
+            let resp = next.clone().run(attempt, extensions).await?;
+            let status = resp.status();
+            let wait = match status {
+                StatusCode::TOO_MANY_REQUESTS => TOO_MANY_REQUESTS_WAIT,
+                StatusCode::IM_A_TEAPOT => BANNED_WAIT,
+                _ => return Ok(resp),
+            };
+            let wait = retry_after(resp.headers()).unwrap_or(wait);
+            if self.pause.set(wait) {
+                let till =
+                    chrono::Utc::now() + chrono::Duration::from_std(wait).unwrap_or_default();
+                if status == StatusCode::IM_A_TEAPOT {
+                    log::error!("{}: IP banned, all calls paused till {}", req.url(), till);
+                } else {
+                    log::warn!("{}: {}, all calls paused till {}", req.url(), status, till);
+                }
+            }
+        }
+    }
+}
+
+// seconds to wait, HTTP dates are not used by the exchanges
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let secs = headers
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(Duration::from_secs(secs))
+}
+
+/// Ordered base URLs of an API.
+/// A call goes to the next URL when the current one keeps failing after the client retries
+#[derive(Debug)]
+pub struct Endpoints {
+    urls: Vec<String>,
+    current: AtomicUsize,
+}
+
+impl Endpoints {
+    /// takes `defaults` if no urls are configured
+    pub fn new(urls: &[String], defaults: &[&str]) -> Result<Endpoints, Box<dyn Error>> {
+        let urls: Vec<String> = if urls.is_empty() {
+            defaults.iter().map(|u| u.to_string()).collect()
+        } else {
+            urls.to_vec()
+        };
+        let urls: Vec<String> = urls
+            .iter()
+            .map(|u| u.trim().trim_end_matches('/').to_string())
+            .filter(|u| !u.is_empty())
+            .collect();
+        if urls.is_empty() {
+            return Err("no exchange url".into());
+        }
+        for url in &urls {
+            reqwest::Url::parse(url).map_err(|e| format!("wrong exchange url '{}': {}", url, e))?;
+        }
+        Ok(Endpoints {
+            urls,
+            current: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn url(&self) -> &str {
+        &self.urls[self.current.load(Ordering::Relaxed) % self.urls.len()]
+    }
+
+    /// calls GET `path` starting from the current url
+    pub async fn get(&self, client: &ClientWithMiddleware, path: &str) -> Result<Response, String> {
+        let start = self.current.load(Ordering::Relaxed);
+        let count = self.urls.len();
+        let mut res = String::new();
+        for i in 0..count {
+            let index = (start + i) % count;
+            let url = format!("{}/{}", self.urls[index], path);
+            log::debug!("Calling... {} ", url);
+            let err = match client.get(&url).send().await {
+                Ok(resp) if resp.status().is_server_error() => {
+                    format!("{}: {}", url, resp.status())
+                }
+                Ok(resp) if resp.status().is_client_error() => {
+                    return Err(client_error(&url, resp).await);
+                }
+                Ok(resp) => {
+                    if index != start % count {
+                        self.current.store(index, Ordering::Relaxed);
+                    }
+                    return Ok(resp);
+                }
+                Err(err) => format!("{}: {}", url, err),
+            };
+            if i + 1 < count {
+                log::warn!("{}, fail over to {}", err, self.urls[(index + 1) % count]);
+            }
+            res = err;
+        }
+        Err(res)
+    }
+}
+
+// the request itself is wrong, other endpoints will not help
+async fn client_error(url: &str, resp: Response) -> String {
+    let status: StatusCode = resp.status();
+    let body = resp.text().await.unwrap_or_default();
+    format!("{}: {}: {}", url, status, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Response as HResponse, Server};
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+
+    // serves the status and counts calls
+    fn serve(status: u16) -> (String, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let int_calls = calls.clone();
+        let make_svc = make_service_fn(move |_conn| {
+            let calls = int_calls.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |_req| {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    async move {
+                        Ok::<_, Infallible>(
+                            HResponse::builder()
+                                .status(status)
+                                .body(Body::from(format!("status {}", status)))
+                                .unwrap(),
+                        )
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_svc);
+        let url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+        (url, calls)
+    }
+
+    fn client() -> ClientWithMiddleware {
+        reqwest_middleware::ClientBuilder::new(reqwest::Client::new()).build()
+    }
+
+    // serves the statuses in turn, the last one after them
+    fn serve_seq(statuses: Vec<(u16, Option<&'static str>)>) -> String {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let statuses = Arc::new(statuses);
+        let make_svc = make_service_fn(move |_conn| {
+            let (calls, statuses) = (calls.clone(), statuses.clone());
+            async move {
+                Ok::<_, Infallible>(service_fn(move |_req| {
+                    let i = calls.fetch_add(1, Ordering::SeqCst).min(statuses.len() - 1);
+                    let (status, retry_after) = statuses[i];
+                    async move {
+                        let mut resp = HResponse::builder().status(status);
+                        if let Some(v) = retry_after {
+                            resp = resp.header("Retry-After", v);
+                        }
+                        Ok::<_, Infallible>(
+                            resp.body(Body::from(format!("status {}", status))).unwrap(),
+                        )
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_svc);
+        let url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+        url
+    }
+
+    fn paused_client(pause: Arc<Pause>) -> ClientWithMiddleware {
+        reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
+            .with(RetryAfterMiddleware { pause })
+            .build()
+    }
+
+    #[test]
+    fn takes_defaults() {
+        let e = Endpoints::new(&[], &["http://a/", "http://b"]).unwrap();
+        assert_eq!(e.urls, vec!["http://a", "http://b"]);
+        let e = Endpoints::new(&["http://c".to_string()], &["http://a"]).unwrap();
+        assert_eq!(e.url(), "http://c");
+        assert!(Endpoints::new(&[], &[]).is_err());
+        assert!(Endpoints::new(&["olia".to_string()], &[]).is_err());
+    }
+
+    #[tokio::test]
+    async fn fails_over() {
+        let (bad, bad_calls) = serve(500);
+        let (good, good_calls) = serve(200);
+        let e = Endpoints::new(&[bad, good.clone()], &[]).unwrap();
+        let resp = e.get(&client(), "ping").await.unwrap();
+        assert_eq!(resp.text().await.unwrap(), "status 200");
+        assert_eq!(e.url(), good);
+        e.get(&client(), "ping").await.unwrap();
+        assert_eq!(bad_calls.load(Ordering::SeqCst), 1);
+        assert_eq!(good_calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn fails_over_unreachable() {
+        let (good, _) = serve(200);
+        let e = Endpoints::new(&["http://127.0.0.1:1".to_string(), good], &[]).unwrap();
+        assert!(e.get(&client(), "ping").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn keeps_url_on_client_error() {
+        let (wrong, _) = serve(400);
+        let (good, good_calls) = serve(200);
+        let e = Endpoints::new(&[wrong.clone(), good], &[]).unwrap();
+        let err = e.get(&client(), "ping").await.unwrap_err();
+        assert!(err.to_string().contains("status 400"));
+        assert_eq!(e.url(), wrong);
+        assert_eq!(good_calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn fails_all() {
+        let (bad, _) = serve(503);
+        let (bad2, _) = serve(502);
+        let e = Endpoints::new(&[bad.clone(), bad2], &[]).unwrap();
+        let err = e.get(&client(), "ping").await.unwrap_err();
+        assert!(err.to_string().contains("502"));
+        assert_eq!(e.url(), bad);
+    }
+
+    #[test]
+    fn parses_retry_after() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+        headers.insert(RETRY_AFTER, "120".parse().unwrap());
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(120)));
+        headers.insert(
+            RETRY_AFTER,
+            "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap(),
+        );
+        assert_eq!(retry_after(&headers), None);
+    }
+
+    #[tokio::test]
+    async fn waits_retry_after() {
+        let url = serve_seq(vec![(429, Some("1")), (200, None)]);
+        let pause = Arc::new(Pause::default());
+        let e = Endpoints::new(&[url], &[]).unwrap();
+        let start = Instant::now();
+        let resp = e.get(&paused_client(pause.clone()), "ping").await.unwrap();
+        assert_eq!(resp.text().await.unwrap(), "status 200");
+        assert!(start.elapsed() >= Duration::from_secs(1));
+        assert!(pause.until.lock().unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn pauses_other_clients() {
+        let url = serve_seq(vec![(200, None)]);
+        let pause = Arc::new(Pause::default());
+        assert!(pause.set(Duration::from_millis(500)));
+        assert!(!pause.set(Duration::from_millis(100)));
+        let e = Endpoints::new(&[url], &[]).unwrap();
+        let start = Instant::now();
+        e.get(&paused_client(pause), "ping").await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(500));
+    }
+
+    #[tokio::test]
+    async fn waits_ban() {
+        let url = serve_seq(vec![(418, Some("1")), (418, Some("0")), (200, None)]);
+        let e = Endpoints::new(&[url], &[]).unwrap();
+        let resp = e
+            .get(&paused_client(Arc::new(Pause::default())), "ping")
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 200);
+    }
+}
@@ -0,0 +1,68 @@
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use cprices::data::{DBSaver, Gap};
+use cprices::{fill_gaps, get_gaps, saver_start, Config, LimiterM, WorkingData};
+use std::error::Error;
+
+use crate::{new_loader, SAVE_BATCH_SIZE, SAVE_BATCH_WAIT};
+
+pub fn command() -> Command {
+    Command::new("gaps")
+        .about("Reports missing klines between imported ones")
+        .arg(
+            Arg::new("repair")
+                .long("repair")
+                .help("Loads the missing klines")
+                .action(ArgAction::SetTrue),
+        )
+}
+
+pub async fn run_command(
+    db: Box<dyn DBSaver + Send + Sync>,
+    limiter: LimiterM,
+    config: &Config,
+    args: &ArgMatches,
+) -> Result<(), Box<dyn Error>> {
+    let mut found: Vec<(String, Vec<Gap>)> = Vec::new();
+    for pair in &config.pairs {
+        let gaps = get_gaps(db.as_ref(), &config.market, pair, config.interval).await?;
+        for gap in &gaps {
+            println!(
+                "{} {} {} {} - {}, missing {}",
+                config.market,
+                pair,
+                config.interval,
+                gap.from,
+                gap.to,
+                gap.missing(config.interval)
+            );
+        }
+        found.push((pair.clone(), gaps));
+    }
+    if !args.get_flag("repair") {
+        return Ok(());
+    }
+
+    let (tx, mut rx) = tokio::sync::mpsc::channel(SAVE_BATCH_SIZE);
+    let saver =
+        tokio::spawn(
+            async move { saver_start(db, &mut rx, SAVE_BATCH_SIZE, SAVE_BATCH_WAIT).await },
+        );
+    for (pair, gaps) in found.into_iter().filter(|(_, gaps)| !gaps.is_empty()) {
+        let w_data = WorkingData {
+            loader: new_loader(config)?,
+            pair,
+            market: config.market.clone(),
+            interval: config.interval,
+            start_from: gaps[0].from,
+            gaps: vec![],
+            sender: tx.clone(),
+            limiter: limiter.clone(),
+            status_db: None,
+        };
+        let count = fill_gaps(&w_data, &gaps).await?;
+        log::info!("{}: loaded {} lines", w_data.pair, count);
+    }
+    drop(tx);
+    saver.await??;
+    Ok(())
+}
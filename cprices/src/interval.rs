@@ -0,0 +1,344 @@
+use chrono::{DateTime, Datelike, Duration, Months, TimeZone, Utc};
+use std::fmt;
+use std::str::FromStr;
+
+/// Binance intervals and Kraken's 15d
+const ALL: &[&str] = &[
+    "1s", "1m", "3m", "5m", "15m", "30m", "1h", "2h", "4h", "6h", "8h", "12h", "1d", "3d", "1w",
+    "1M", "15d",
+];
+// 1970-01-05 is the first Monday after the epoch
+const WEEK_OFFSET_MS: i64 = 4 * 86_400_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Unit {
+    Second,
+    Minute,
+    Hour,
+    Day,
+    Week,
+    Month,
+}
+
+/// Kline interval in the Binance notation, e.g. 1m, 4h, 1w or 1M (a calendar month).
+/// Fixed intervals open at multiples of their length since the epoch,
+/// weeks on Monday and months on the first day, all in UTC
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Interval {
+    count: u32,
+    unit: Unit,
+}
+
+impl FromStr for Interval {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if !ALL.contains(&value) {
+            return Err(format!(
+                "wrong interval '{}', use one of {}",
+                value,
+                ALL.join(", ")
+            ));
+        }
+        let (count, unit) = value.split_at(value.len() - 1);
+        let unit = match unit {
+            "s" => Unit::Second,
+            "m" => Unit::Minute,
+            "h" => Unit::Hour,
+            "d" => Unit::Day,
+            "w" => Unit::Week,
+            _ => Unit::Month,
+        };
+        let count = count
+            .parse()
+            .map_err(|e| format!("wrong interval '{}': {}", value, e))?;
+        Ok(Interval { count, unit })
+    }
+}
+
+impl fmt::Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unit = match self.unit {
+            Unit::Second => "s",
+            Unit::Minute => "m",
+            Unit::Hour => "h",
+            Unit::Day => "d",
+            Unit::Week => "w",
+            Unit::Month => "M",
+        };
+        write!(f, "{}{}", self.count, unit)
+    }
+}
+
+impl Interval {
+    // length of a fixed interval in ms, None for months
+    fn fixed_ms(&self) -> Option<i64> {
+        let unit = match self.unit {
+            Unit::Second => 1000,
+            Unit::Minute => 60_000,
+            Unit::Hour => 3_600_000,
+            Unit::Day => 86_400_000,
+            Unit::Week => 7 * 86_400_000,
+            Unit::Month => return None,
+        };
+        Some(unit * self.count as i64)
+    }
+
+    /// longest possible length, 31 days for a month
+    pub fn max_duration(&self) -> Duration {
+        match self.fixed_ms() {
+            Some(ms) => Duration::milliseconds(ms),
+            None => Duration::days(31 * self.count as i64),
+        }
+    }
+
+    /// open time of the interval containing the time
+    pub fn open_time(&self, time: DateTime<Utc>) -> DateTime<Utc> {
+        match self.fixed_ms() {
+            Some(step) => {
+                let offset = if self.unit == Unit::Week {
+                    WEEK_OFFSET_MS
+                } else {
+                    0
+                };
+                let ms = time.timestamp_millis() - offset;
+                Utc.timestamp_millis_opt(ms - ms.rem_euclid(step) + offset)
+                    .unwrap()
+            }
+            None => Utc
+                .with_ymd_and_hms(time.year(), time.month(), 1, 0, 0, 0)
+                .unwrap(),
+        }
+    }
+
+    /// open time of the interval after the one opening at `open`,
+    /// fixed intervals are not realigned as some exchanges open weeks on Thursday
+    pub fn next_open(&self, open: DateTime<Utc>) -> DateTime<Utc> {
+        match self.fixed_ms() {
+            Some(step) => open + Duration::milliseconds(step),
+            None => self.open_time(open) + Months::new(self.count),
+        }
+    }
+
+    /// open time `count` intervals after `open`
+    pub fn after(&self, open: DateTime<Utc>, count: u32) -> DateTime<Utc> {
+        match self.fixed_ms() {
+            Some(step) => open + Duration::milliseconds(step * count as i64),
+            None => (0..count).fold(open, |t, _| self.next_open(t)),
+        }
+    }
+
+    /// last millisecond of the interval opening at `open`
+    pub fn close_time(&self, open: DateTime<Utc>) -> DateTime<Utc> {
+        self.next_open(open) - Duration::milliseconds(1)
+    }
+
+    /// count of intervals opening in [from, to)
+    pub fn count(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> i64 {
+        if to <= from {
+            return 0;
+        }
+        match self.fixed_ms() {
+            Some(step) => ((to - from).num_milliseconds() + step - 1) / step,
+            None => {
+                let month = |t: DateTime<Utc>| t.year() as i64 * 12 + t.month0() as i64;
+                let mut res = month(to) - month(from);
+                if self.open_time(to) < to {
+                    res += 1;
+                }
+                if self.open_time(from) < from {
+                    res -= 1;
+                }
+                (res + self.count as i64 - 1) / self.count as i64
+            }
+        }
+    }
+
+    /// Binance notation
+    pub fn binance(&self) -> Result<String, String> {
+        match self.unit == Unit::Day && self.count == 15 {
+            true => Err(self.unsupported("binance", &ALL[..ALL.len() - 1])),
+            false => Ok(self.to_string()),
+        }
+    }
+
+    /// OKX bar, 6h and longer bars aligned to UTC
+    pub fn okx(&self) -> Result<&'static str, String> {
+        match self.to_string().as_str() {
+            "1m" => Ok("1m"),
+            "3m" => Ok("3m"),
+            "5m" => Ok("5m"),
+            "15m" => Ok("15m"),
+            "30m" => Ok("30m"),
+            "1h" => Ok("1H"),
+            "2h" => Ok("2H"),
+            "4h" => Ok("4H"),
+            "6h" => Ok("6Hutc"),
+            "12h" => Ok("12Hutc"),
+            "1d" => Ok("1Dutc"),
+            "1w" => Ok("1Wutc"),
+            "1M" => Ok("1Mutc"),
+            _ => Err(self.unsupported(
+                "okx",
+                &[
+                    "1m", "3m", "5m", "15m", "30m", "1h", "2h", "4h", "6h", "12h", "1d", "1w", "1M",
+                ],
+            )),
+        }
+    }
+
+    /// Coinbase granularity in seconds
+    pub fn coinbase(&self) -> Result<i64, String> {
+        match self.to_string().as_str() {
+            "1m" | "5m" | "15m" | "1h" | "6h" | "1d" => Ok(self.max_duration().num_seconds()),
+            _ => Err(self.unsupported("coinbase", &["1m", "5m", "15m", "1h", "6h", "1d"])),
+        }
+    }
+
+    /// Kraken OHLC interval in minutes
+    pub fn kraken(&self) -> Result<i64, String> {
+        match self.to_string().as_str() {
+            "1m" | "5m" | "15m" | "30m" | "1h" | "4h" | "1d" | "1w" | "15d" => {
+                Ok(self.max_duration().num_minutes())
+            }
+            _ => Err(self.unsupported(
+                "kraken",
+                &["1m", "5m", "15m", "30m", "1h", "4h", "1d", "1w", "15d"],
+            )),
+        }
+    }
+
+    fn unsupported(&self, exchange: &str, allowed: &[&str]) -> String {
+        format!(
+            "interval '{}' is not supported by {}, use one of {}",
+            self,
+            exchange,
+            allowed.join(", ")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interval(value: &str) -> Interval {
+        value.parse().unwrap()
+    }
+
+    fn time(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value).unwrap().into()
+    }
+
+    #[test]
+    fn parses() {
+        for value in ALL {
+            assert_eq!(interval(value).to_string(), *value);
+        }
+        assert_ne!(interval("1m"), interval("1M"));
+        for value in ["", "1", "m", "2m", "1y", "1H", "olia"] {
+            let err = value.parse::<Interval>().unwrap_err();
+            assert!(err.contains("use one of 1s, 1m"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn aligns_fixed() {
+        let t = time("2023-03-15T10:37:12.345Z");
+        assert_eq!(interval("1s").open_time(t), time("2023-03-15T10:37:12Z"));
+        assert_eq!(interval("15m").open_time(t), time("2023-03-15T10:30:00Z"));
+        assert_eq!(interval("8h").open_time(t), time("2023-03-15T08:00:00Z"));
+        // epoch aligned, 2023-03-15 is the day 19431
+        assert_eq!(
+            interval("3d").open_time(time("2023-03-17T23:00:00Z")),
+            time("2023-03-15T00:00:00Z")
+        );
+        let open = time("2023-03-15T10:30:00Z");
+        assert_eq!(
+            interval("15m").next_open(open),
+            time("2023-03-15T10:45:00Z")
+        );
+        assert_eq!(interval("1d").next_open(open), time("2023-03-16T10:30:00Z"));
+        assert_eq!(interval("15m").after(open, 4), time("2023-03-15T11:30:00Z"));
+        assert_eq!(
+            interval("1h").close_time(open),
+            time("2023-03-15T11:29:59.999Z")
+        );
+    }
+
+    #[test]
+    fn aligns_week_to_monday() {
+        let w = interval("1w");
+        // Wednesday
+        let t = time("2023-03-15T10:37:12Z");
+        assert_eq!(w.open_time(t), time("2023-03-13T00:00:00Z"));
+        let monday = time("2023-03-13T00:00:00Z");
+        assert_eq!(w.open_time(monday), monday);
+        assert_eq!(w.next_open(monday), time("2023-03-20T00:00:00Z"));
+        assert_eq!(
+            w.open_time(time("1970-01-01T00:00:00Z")),
+            time("1969-12-29T00:00:00Z")
+        );
+    }
+
+    #[test]
+    fn aligns_month_to_calendar() {
+        let m = interval("1M");
+        assert_eq!(
+            m.open_time(time("2023-02-15T10:00:00Z")),
+            time("2023-02-01T00:00:00Z")
+        );
+        assert_eq!(
+            m.next_open(time("2023-01-31T23:59:59Z")),
+            time("2023-02-01T00:00:00Z")
+        );
+        assert_eq!(
+            m.next_open(time("2023-02-01T00:00:00Z")),
+            time("2023-03-01T00:00:00Z")
+        );
+        assert_eq!(
+            m.next_open(time("2024-02-01T00:00:00Z")),
+            time("2024-03-01T00:00:00Z")
+        );
+        assert_eq!(
+            m.next_open(time("2023-12-01T00:00:00Z")),
+            time("2024-01-01T00:00:00Z")
+        );
+        assert_eq!(
+            m.close_time(time("2023-02-01T00:00:00Z")),
+            time("2023-02-28T23:59:59.999Z")
+        );
+        assert_eq!(m.max_duration(), Duration::days(31));
+        assert_eq!(
+            m.after(time("2023-01-01T00:00:00Z"), 3),
+            time("2023-04-01T00:00:00Z")
+        );
+    }
+
+    #[test]
+    fn counts() {
+        let (from, to) = (time("2023-01-01T00:00:00Z"), time("2023-04-01T00:00:00Z"));
+        assert_eq!(interval("1M").count(from, to), 3);
+        assert_eq!(interval("1M").count(from, time("2023-03-02T00:00:00Z")), 3);
+        assert_eq!(interval("1M").count(to, from), 0);
+        assert_eq!(interval("1d").count(from, to), 90);
+        assert_eq!(interval("1h").count(from, time("2023-01-01T02:30:00Z")), 3);
+    }
+
+    #[test]
+    fn converts_to_exchanges() {
+        assert_eq!(interval("1M").binance().unwrap(), "1M");
+        assert_eq!(interval("1s").binance().unwrap(), "1s");
+        assert!(interval("15d").binance().is_err());
+        assert_eq!(interval("1h").okx().unwrap(), "1H");
+        assert_eq!(interval("1d").okx().unwrap(), "1Dutc");
+        assert_eq!(interval("1M").okx().unwrap(), "1Mutc");
+        assert!(interval("8h").okx().is_err());
+        assert_eq!(interval("6h").coinbase().unwrap(), 21600);
+        assert!(interval("4h").coinbase().is_err());
+        assert_eq!(interval("15d").kraken().unwrap(), 21600);
+        assert_eq!(interval("1w").kraken().unwrap(), 10080);
+        let err = interval("1M").kraken().unwrap_err();
+        assert!(err.contains("not supported by kraken"), "{}", err);
+    }
+}
@@ -0,0 +1,324 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use cprices::data::{KLine, Loader};
+use cprices::interval::Interval;
+use reqwest_middleware::ClientWithMiddleware;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use tokio::sync::Mutex;
+
+use crate::binance::string_as_f64;
+use crate::endpoints::{http_client, Endpoints};
+
+pub const MARKET: &str = "kraken";
+/// allowed requests per minute, public endpoints allow about 1 per second
+pub const REQUESTS_PER_MINUTE: u32 = 30;
+const URLS: &[&str] = &["https://api.kraken.com"];
+
+/// Loads Kraken OHLC, pairs as BTCUSD or XBTUSD.
+/// Kraken keeps the last 720 entries of an interval only
+pub struct Kraken {
+    endpoints: Endpoints,
+    client: ClientWithMiddleware,
+    // `last` cursor of a pair and interval, open time of the last committed entry in seconds
+    cursors: Mutex<HashMap<(String, String), i64>>,
+}
+
+impl Kraken {
+    pub fn new(urls: &[String]) -> Result<Kraken, Box<dyn Error>> {
+        Ok(Kraken {
+            endpoints: Endpoints::new(urls, URLS)?,
+            client: http_client()?,
+            cursors: Mutex::new(HashMap::new()),
+        })
+    }
+}
+
+#[async_trait]
+impl Loader for Kraken {
+    async fn live(&self) -> std::result::Result<String, Box<dyn Error>> {
+        let content = self
+            .endpoints
+            .get(&self.client, "0/public/Time")
+            .await?
+            .text()
+            .await?;
+        log::debug!("{} response: {}", self.endpoints.url(), content);
+        Ok(content)
+    }
+    async fn retrieve(
+        &self,
+        pair: &str,
+        interval: Interval,
+        from: DateTime<Utc>,
+    ) -> std::result::Result<Vec<KLine>, Box<dyn Error>> {
+        let minutes = interval.kraken()?;
+        let interval = &interval.to_string();
+        let key = (pair.to_string(), interval.to_string());
+        let from_s = from.timestamp();
+        let cursor = self.cursors.lock().await.get(&key).copied();
+        let path = format!(
+            "0/public/OHLC?pair={}&interval={}&since={}",
+            request_pair(pair),
+            minutes,
+            since(cursor, from_s, minutes * 60)
+        );
+        let resp = self
+            .endpoints
+            .get(&self.client, &path)
+            .await?
+            .json::<KrakenResponse>()
+            .await?;
+        let (data, last) = parse_result(resp, pair)?;
+        if let Some(first) = data.first() {
+            if first.time > from_s + minutes * 60 {
+                log::warn!(
+                    "kraken {} {}: no entries from {}, it keeps the last 720 only",
+                    pair,
+                    interval,
+                    from
+                );
+            }
+        }
+        self.cursors.lock().await.insert(key, last);
+        Ok(to_klines(&data, pair, interval, minutes * 60, last)
+            .into_iter()
+            .filter(|l| l.open_time >= from_s * 1000)
+            .collect())
+    }
+}
+
+// Kraken returns entries newer than since, the cursor continues the previous poll
+fn since(cursor: Option<i64>, from: i64, step: i64) -> i64 {
+    match cursor {
+        Some(last) if last < from && from - last <= step => last,
+        _ => from - 1,
+    }
+}
+
+// Kraken names bitcoin XBT and dogecoin XDG
+fn request_pair(pair: &str) -> String {
+    let pair = pair.replace('/', "");
+    let pair = match pair.strip_prefix("BTC") {
+        Some(quote) => format!("XBT{}", quote),
+        None => pair,
+    };
+    let pair = match pair.strip_prefix("DOGE") {
+        Some(quote) => format!("XDG{}", quote),
+        None => pair,
+    };
+    match pair.strip_suffix("BTC") {
+        Some(base) => format!("{}XBT", base),
+        None => pair,
+    }
+}
+
+/// Maps Kraken asset names to common ones, e.g. XXBT to BTC
+fn asset_name(asset: &str) -> &str {
+    // legacy 4 letter names start with X for crypto and Z for fiat
+    let asset = match asset.len() {
+        4 if asset.starts_with('X') || asset.starts_with('Z') => &asset[1..],
+        _ => asset,
+    };
+    match asset {
+        "XBT" => "BTC",
+        "XDG" => "DOGE",
+        _ => asset,
+    }
+}
+
+const QUOTES: &[&str] = &[
+    "ZUSD", "ZEUR", "ZGBP", "ZJPY", "ZCAD", "XXBT", "XETH", "USDT", "USDC", "USD", "EUR", "GBP",
+    "JPY", "CAD", "CHF", "AUD", "XBT", "ETH", "DAI",
+];
+
+/// Maps Kraken pair names to common ones, e.g. XXBTZUSD to BTCUSD
+fn pair_name(kraken_pair: &str) -> String {
+    let quote = QUOTES
+        .iter()
+        .find(|q| kraken_pair.len() > q.len() && kraken_pair.ends_with(*q))
+        .copied()
+        .unwrap_or(&kraken_pair[kraken_pair.len().saturating_sub(3)..]);
+    let base = &kraken_pair[..kraken_pair.len() - quote.len()];
+    format!("{}{}", asset_name(base), asset_name(quote))
+}
+
+#[derive(Debug, Deserialize)]
+struct KrakenResponse {
+    error: Vec<String>,
+    #[serde(default)]
+    result: HashMap<String, serde_json::Value>,
+}
+
+// returns entries of the pair and the `last` cursor
+fn parse_result(resp: KrakenResponse, pair: &str) -> Result<(Vec<KrakenOhlc>, i64), String> {
+    if !resp.error.is_empty() {
+        return Err(format!("kraken {}: {}", pair, resp.error.join(", ")));
+    }
+    let last = resp
+        .result
+        .get("last")
+        .and_then(|v| v.as_i64())
+        .ok_or_else(|| format!("kraken {}: no last in response", pair))?;
+    let wanted = pair_name(&request_pair(pair));
+    let data = resp
+        .result
+        .iter()
+        .find(|(k, _)| k.as_str() != "last" && pair_name(k) == wanted)
+        .or_else(|| resp.result.iter().find(|(k, _)| k.as_str() != "last"))
+        .map(|(_, v)| v.clone())
+        .ok_or_else(|| format!("kraken {}: no data in response", pair))?;
+    let data = serde_json::from_value::<Vec<KrakenOhlc>>(data)
+        .map_err(|e| format!("kraken {}: {}", pair, e))?;
+    Ok((data, last))
+}
+
+// the final entry is the current frame, it is always sent and never committed
+fn to_klines(data: &[KrakenOhlc], pair: &str, interval: &str, step: i64, last: i64) -> Vec<KLine> {
+    let mut res: Vec<KLine> = data
+        .iter()
+        .map(|d| to_kline(d, pair, interval, step, last))
+        .collect();
+    if let Some(l) = res.last_mut() {
+        l.is_closed = false;
+    }
+    res
+}
+
+fn to_kline(d: &KrakenOhlc, pair: &str, interval: &str, step: i64, last: i64) -> KLine {
+    let open_time = d.time * 1000;
+    KLine {
+        open_time,
+        open_price: d.open,
+        high_price: d.high,
+        low_price: d.low,
+        close_price: d.close,
+        volume: d.volume,
+        close_time: open_time + step * 1000 - 1,
+        quote_volume: d.vwap * d.volume,
+        trades: d.count,
+        // not provided by kraken
+        taker_buy_volume: 0.0,
+        taker_buy_quote_volume: 0.0,
+        pair: pair.to_string(),
+        interval: interval.to_string(),
+        market: MARKET.to_string(),
+        // entries after the `last` cursor are not committed yet
+        is_closed: d.time <= last,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct KrakenOhlc {
+    // [ time, open, high, low, close, vwap, volume, count ]
+    pub time: i64,
+    #[serde(deserialize_with = "string_as_f64")]
+    pub open: f64,
+    #[serde(deserialize_with = "string_as_f64")]
+    pub high: f64,
+    #[serde(deserialize_with = "string_as_f64")]
+    pub low: f64,
+    #[serde(deserialize_with = "string_as_f64")]
+    pub close: f64,
+    #[serde(deserialize_with = "string_as_f64")]
+    pub vwap: f64,
+    #[serde(deserialize_with = "string_as_f64")]
+    pub volume: f64,
+    pub count: i64,
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+
+    use crate::kraken::{
+        asset_name, pair_name, parse_result, request_pair, since, to_kline, to_klines,
+        KrakenResponse,
+    };
+
+    // GET 0/public/OHLC?pair=XBTUSD&interval=60&since=1696114799
+    fn sample() -> &'static str {
+        r#"{"error":[],"result":{"XXBTZUSD":[
+        [1696114800,"26967.3","26975.0","26902.0","26962.6","26941.2","98.04316411",1423],
+        [1696118400,"26962.6","26998.2","26931.6","26985.4","26960.8","154.40312970",2011],
+        [1696122000,"26985.4","27010.5","26950.0","27002.1","26981.7","12.63210517",301]
+        ],"last":1696118400}}"#
+    }
+
+    #[test]
+    fn parses_response() {
+        let resp: KrakenResponse = serde_json::from_str(sample()).unwrap();
+        let (data, last) = parse_result(resp, "BTCUSD").unwrap();
+        assert_eq!(last, 1696118400);
+        assert_eq!(data.len(), 3);
+        assert_eq!(data[0].time, 1696114800);
+        assert_relative_eq!(data[0].open, 26967.3);
+        assert_relative_eq!(data[0].high, 26975.0);
+        assert_relative_eq!(data[0].low, 26902.0);
+        assert_relative_eq!(data[0].close, 26962.6);
+        assert_relative_eq!(data[0].vwap, 26941.2);
+        assert_relative_eq!(data[0].volume, 98.04316411);
+        assert_eq!(data[0].count, 1423);
+    }
+
+    #[test]
+    fn parses_error() {
+        let resp: KrakenResponse =
+            serde_json::from_str(r#"{"error":["EQuery:Unknown asset pair"]}"#).unwrap();
+        let err = parse_result(resp, "OLIAUSD").unwrap_err();
+        assert!(err.contains("EQuery:Unknown asset pair"));
+    }
+
+    #[test]
+    fn maps_to_kline() {
+        let resp: KrakenResponse = serde_json::from_str(sample()).unwrap();
+        let (data, last) = parse_result(resp, "BTCUSD").unwrap();
+        let kline = to_kline(&data[1], "BTCUSD", "1h", 3600, last);
+        assert_eq!(kline.open_time, 1696118400000);
+        assert_eq!(kline.close_time, 1696121999999);
+        assert_relative_eq!(kline.quote_volume, 26960.8 * 154.4031297);
+        assert_eq!(kline.trades, 2011);
+        assert_eq!(kline.pair, "BTCUSD");
+        assert_eq!(kline.market, "kraken");
+        assert!(kline.is_closed);
+    }
+
+    #[test]
+    fn marks_last_entry_open() {
+        let resp: KrakenResponse = serde_json::from_str(sample()).unwrap();
+        let (data, last) = parse_result(resp, "BTCUSD").unwrap();
+        let kline = to_kline(&data[2], "BTCUSD", "1h", 3600, last);
+        assert!(!kline.is_closed);
+        let klines = to_klines(&data, "BTCUSD", "1h", 3600, 1696122000);
+        assert_eq!(klines.len(), 3);
+        assert!(klines[1].is_closed);
+        assert!(!klines[2].is_closed);
+    }
+
+    #[test]
+    fn maps_names() {
+        assert_eq!(request_pair("BTCUSD"), "XBTUSD");
+        assert_eq!(request_pair("BTC/USD"), "XBTUSD");
+        assert_eq!(request_pair("DOGEUSD"), "XDGUSD");
+        assert_eq!(request_pair("ETHBTC"), "ETHXBT");
+        assert_eq!(request_pair("ETHUSD"), "ETHUSD");
+        assert_eq!(asset_name("XXBT"), "BTC");
+        assert_eq!(asset_name("ZUSD"), "USD");
+        assert_eq!(asset_name("XBT"), "BTC");
+        assert_eq!(asset_name("DOT"), "DOT");
+        assert_eq!(pair_name("XXBTZUSD"), "BTCUSD");
+        assert_eq!(pair_name("XETHXXBT"), "ETHBTC");
+        assert_eq!(pair_name("DOTUSD"), "DOTUSD");
+        assert_eq!(pair_name("XDGUSD"), "DOGEUSD");
+        assert_eq!(pair_name("XBTUSDT"), "BTCUSDT");
+    }
+
+    #[test]
+    fn uses_cursor() {
+        assert_eq!(since(None, 1000, 60), 999);
+        assert_eq!(since(Some(940), 1000, 60), 940);
+        assert_eq!(since(Some(100), 1000, 60), 999);
+        assert_eq!(since(Some(1000), 1000, 60), 999);
+    }
+}
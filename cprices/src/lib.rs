@@ -1,9 +1,17 @@
+pub mod clock;
 pub mod data;
+pub mod interval;
+pub mod metrics;
+pub mod validate;
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
 use clap::ArgMatches;
-use data::{DBSaver, KLine, Limiter, Loader};
+use data::{DBSaver, Gap, KLine, Limiter, Loader, SymbolStatus, TRADING};
+use interval::Interval;
+use std::collections::HashMap;
 use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::watch;
 use tokio::sync::{
     mpsc::{Receiver, Sender},
@@ -12,38 +20,103 @@ use tokio::sync::{
 
 pub struct Config {
     pub pairs: Vec<String>,
-    pub interval: String,
+    pub interval: Interval,
+    /// market of the exchange, selects the loader
+    pub market: String,
+    /// exchange API base URLs in failover order, the loader's defaults if empty
+    pub urls: Vec<String>,
     pub db_url: String,
+    /// start time for pairs without data in DB
+    pub since: Option<DateTime<Utc>>,
+    /// per pair start time, overrides `since` and `since_listing`
+    pub pair_since: HashMap<String, DateTime<Utc>>,
+    /// start from the first kline available in the exchange
+    pub since_listing: bool,
 }
 
 impl Config {
-    pub fn build(args: &ArgMatches) -> Result<Config, &'static str> {
+    pub fn build(args: &ArgMatches) -> Result<Config, String> {
         let pair = args.get_one::<String>("pair").expect("no pair param");
         let interval = args
             .get_one::<String>("interval")
             .expect("no interval param");
+        let market = args.get_one::<String>("market").expect("no market param");
         let db_url = args
             .get_one::<String>("db_url")
             .expect("no db_url provided");
         let pairs = pair.split(',').map(String::from).collect();
+        let urls = args
+            .get_one::<String>("exchange_url")
+            .map(|v| {
+                v.split(',')
+                    .map(|s| s.trim().to_string())
+                    .filter(|s| !s.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let since = match args.get_one::<String>("since") {
+            Some(v) => Some(parse_time(v)?),
+            None => None,
+        };
+        let pair_since = match args.get_one::<String>("pair_since") {
+            Some(v) => parse_pair_since(v)?,
+            None => HashMap::new(),
+        };
         Ok(Config {
             pairs,
-            interval: interval.to_string(),
+            interval: interval.parse()?,
+            market: market.to_string(),
+            urls,
             db_url: db_url.to_string(),
+            since,
+            pair_since,
+            since_listing: args.get_flag("since_listing"),
         })
     }
 }
 
-type LimiterM = std::sync::Arc<Mutex<Box<dyn Limiter>>>;
+/// Parses RFC 3339 time or a date as 2017-08-17
+pub fn parse_time(value: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map(|d| Utc.from_utc_datetime(&d.and_hms_opt(0, 0, 0).unwrap()))
+        .map_err(|e| format!("wrong time '{}': {}", value, e))
+}
+
+/// Parses pair start times as BTCUSDT=2017-08-17,ETHUSDT=2017-08-17
+pub fn parse_pair_since(value: &str) -> Result<HashMap<String, DateTime<Utc>>, String> {
+    let mut res = HashMap::new();
+    for item in value.split(',').filter(|s| !s.trim().is_empty()) {
+        let (pair, time) = item
+            .split_once('=')
+            .ok_or_else(|| format!("wrong pair since '{}', expected PAIR=TIME", item))?;
+        res.insert(pair.trim().to_string(), parse_time(time.trim())?);
+    }
+    Ok(res)
+}
+
+pub type LimiterM = Arc<Mutex<Box<dyn Limiter>>>;
 type ResultM = Result<(), Box<dyn Error>>;
 
+// imports without new klines before asking the exchange whether the pair still trades
+const EMPTY_IMPORTS_TO_CHECK: u32 = 3;
+// how often to check if a halted pair trades again
+const HALTED_CHECK_EVERY: Duration = Duration::from_secs(300);
+
 pub struct WorkingData {
     pub pair: String,
-    pub interval: String,
+    pub market: String,
+    pub interval: Interval,
     pub start_from: DateTime<Utc>,
+    /// holes to backfill before importing new klines
+    pub gaps: Vec<Gap>,
     pub loader: Box<dyn Loader>,
     pub limiter: LimiterM,
     pub sender: Sender<KLine>,
+    /// where to record the pair's exchange status changes, not recorded if None
+    pub status_db: Option<Arc<dyn DBSaver + Send + Sync>>,
 }
 
 pub async fn run_exit_indicator(w_data: WorkingData, close_ch: watch::Receiver<i32>, exit_ind: tokio::sync::mpsc::UnboundedSender<i32>) -> ResultM {
@@ -74,10 +147,16 @@ pub async fn run(w_data: WorkingData, mut close_ch: watch::Receiver<i32>) -> Res
         }
     }
     let mut last_time = w_data.start_from;
-    let dur = chrono::Duration::from_std(
-        duration_str::parse(&w_data.interval).map_err(|e| format!("duration  parse: {}", e))?,
-    )
-    .map_err(|e| format!("duration parse: {}", e))?;
+    let mut empty = 0;
+    if !w_data.gaps.is_empty() {
+        let count = fill_gaps(&w_data, &w_data.gaps).await?;
+        log::info!(
+            "filled {} gaps of {} with {} lines",
+            w_data.gaps.len(),
+            w_data.pair,
+            count
+        );
+    }
 
     loop {
         log::info!("loop");
@@ -90,14 +169,34 @@ pub async fn run(w_data: WorkingData, mut close_ch: watch::Receiver<i32>) -> Res
         }
         log::info!("after check");
         let max_dur = chrono::Duration::minutes(15);
-        let mut td = last_time - (Utc::now() - dur);
+        let mut td = w_data.interval.next_open(last_time) - clock::now();
         if td < chrono::Duration::zero() {
-            last_time = import(&w_data, last_time).await?;
+            let halted = match import(&w_data, last_time).await {
+                Ok(res) => {
+                    empty = if res > last_time { 0 } else { empty + 1 };
+                    last_time = res;
+                    if empty < EMPTY_IMPORTS_TO_CHECK {
+                        continue;
+                    }
+                    empty = 0;
+                    halted_status(&w_data).await?
+                }
+                // a delisted pair may fail instead of returning nothing
+                Err(err) => match halted_status(&w_data).await {
+                    Ok(Some(status)) => Some(status),
+                    _ => return Err(err),
+                },
+            };
+            if let Some(status) = halted {
+                if !wait_trading(&w_data, status, &mut close_ch).await? {
+                    break;
+                }
+            }
         } else {
             if td > max_dur {
                 td = max_dur;
             }
-            log::info!("sleep till {}", Utc::now() + td);
+            log::info!("sleep till {}", clock::now() + td);
             let sleep = tokio::time::sleep(td.to_std()?);
             tokio::pin!(sleep);
             tokio::select! {
@@ -114,24 +213,250 @@ pub async fn run(w_data: WorkingData, mut close_ch: watch::Receiver<i32>) -> Res
     Ok(())
 }
 
-pub async fn get_last_time(
+// the pair's exchange status if it is not trading
+async fn halted_status(w_data: &WorkingData) -> Result<Option<String>, Box<dyn Error>> {
+    w_data
+        .limiter
+        .lock()
+        .await
+        .wait(w_data.loader.status_weight())
+        .await?;
+    let status = w_data.loader.status(&w_data.pair).await?;
+    if let Some(used) = w_data.loader.used_weight() {
+        w_data.limiter.lock().await.update(used);
+    }
+    match status {
+        Some(status) if status != TRADING => Ok(Some(status)),
+        _ => Ok(None),
+    }
+}
+
+// stops importing the halted pair until it trades again, false if closed meanwhile
+async fn wait_trading(
+    w_data: &WorkingData,
+    status: String,
+    close_ch: &mut watch::Receiver<i32>,
+) -> Result<bool, Box<dyn Error>> {
+    log::warn!("{} is not trading: {}, stop importing", w_data.pair, status);
+    save_status(w_data, &status).await;
+    loop {
+        let sleep = tokio::time::sleep(HALTED_CHECK_EVERY);
+        tokio::pin!(sleep);
+        tokio::select! {
+            _ = &mut sleep => {},
+            cr = close_ch.changed() => {
+                if let Err(err) = cr {
+                    log::debug!("got watcher err {}", err);
+                    return Ok(false);
+                }
+                continue;
+            }
+        }
+        match halted_status(w_data).await {
+            Ok(None) => break,
+            Ok(Some(status)) => log::debug!("{} is still not trading: {}", w_data.pair, status),
+            Err(err) => log::warn!("check {} status: {}", w_data.pair, err),
+        }
+    }
+    log::info!("{} is trading again, resume importing", w_data.pair);
+    save_status(w_data, TRADING).await;
+    Ok(true)
+}
+
+async fn save_status(w_data: &WorkingData, status: &str) {
+    let Some(db) = &w_data.status_db else {
+        return;
+    };
+    let data = SymbolStatus {
+        time: Utc::now(),
+        pair: w_data.pair.clone(),
+        market: w_data.market.clone(),
+        status: status.to_string(),
+    };
+    if let Err(err) = db.save_status(&data).await {
+        log::error!("save {} status: {}", w_data.pair, err);
+    }
+}
+
+/// measures the exchange clock offset used by `clock::now`, None if the loader can not tell
+pub async fn sync_clock(
+    loader: &dyn Loader,
+    max_skew: chrono::Duration,
+) -> Result<Option<chrono::Duration>, Box<dyn Error>> {
+    let before = Utc::now();
+    let Some(server) = loader.server_time().await? else {
+        return Ok(None);
+    };
+    clock::set_offset(clock::estimate(before, server, Utc::now()));
+    let offset = clock::offset();
+    metrics::CLOCK_OFFSET.set(offset.num_milliseconds() as f64 / 1000.0);
+    if offset.abs() > max_skew {
+        log::warn!(
+            "exchange clock is {}ms {} the local one",
+            offset.num_milliseconds().abs(),
+            if offset > chrono::Duration::zero() {
+                "ahead of"
+            } else {
+                "behind"
+            }
+        );
+    } else {
+        log::debug!("clock offset {}ms", offset.num_milliseconds());
+    }
+    Ok(Some(offset))
+}
+
+/// keeps the clock offset up to date
+pub async fn run_clock_sync(
+    loader: Box<dyn Loader>,
+    limiter: LimiterM,
+    every: Duration,
+    max_skew: chrono::Duration,
+    mut close_ch: watch::Receiver<i32>,
+) -> ResultM {
+    loop {
+        let sleep = tokio::time::sleep(every);
+        tokio::pin!(sleep);
+        tokio::select! {
+            _ = &mut sleep => {},
+            cr = close_ch.changed() => {
+                if let Err(err) = cr {
+                    log::debug!("got watcher err {}", err);
+                    break;
+                }
+                continue;
+            }
+        }
+        limiter.lock().await.wait(1).await?;
+        match sync_clock(loader.as_ref(), max_skew).await {
+            Ok(Some(_)) => {}
+            Ok(None) => break,
+            Err(err) => log::warn!("clock sync: {}", err),
+        }
+    }
+    Ok(())
+}
+
+pub async fn get_start_time(
     db: &'_ (dyn DBSaver + Send + Sync),
+    loader: &dyn Loader,
+    config: &Config,
     pair: &str,
 ) -> Result<DateTime<Utc>, Box<dyn Error>> {
-    log::info!("Get last value in DB for {}", pair);
-    db.get_last_time(pair)
+    log::info!(
+        "Get last value in DB for {} {} {}",
+        config.market,
+        pair,
+        config.interval
+    );
+    let last = db
+        .get_last_time(&config.market, pair, &config.interval.to_string())
+        .await
+        .map_err(|e| format!("get pair's '{}' ({}) from: {}", pair, config.interval, e))?;
+    if let Some(last) = last {
+        return Ok(last);
+    }
+    if let Some(since) = config.pair_since.get(pair) {
+        log::info!("No data for {}, start from {}", pair, since);
+        return Ok(*since);
+    }
+    let mut res = config
+        .since
+        .unwrap_or_else(|| Utc.timestamp_opt(0, 0).unwrap());
+    if config.since_listing {
+        let first = loader
+            .first_time(pair, config.interval)
+            .await
+            .map_err(|e| format!("get pair's '{}' first kline: {}", pair, e))?;
+        match first {
+            Some(first) if first > res => res = first,
+            Some(_) => {}
+            None => log::warn!("No first kline info for {}", pair),
+        }
+    }
+    log::info!("No data for {}, start from {}", pair, res);
+    Ok(res)
+}
+
+pub async fn get_gaps(
+    db: &'_ (dyn DBSaver + Send + Sync),
+    market: &str,
+    pair: &str,
+    interval: Interval,
+) -> Result<Vec<Gap>, Box<dyn Error>> {
+    log::info!("Look for gaps in DB for {} {} {}", market, pair, interval);
+    let step = interval.max_duration();
+    let res: Vec<Gap> = db
+        .get_gaps(market, pair, &interval.to_string(), step)
         .await
-        .map_err(|e| format!("get pair's '{}' from: {}", pair, e).into())
+        .map_err(|e| format!("get pair's '{}' ({}) gaps: {}", pair, interval, e))?
+        .into_iter()
+        // the DB adds the longest length to the last kline, a month may be shorter
+        .map(|gap| Gap {
+            from: interval.next_open(gap.from - step),
+            to: gap.to,
+        })
+        .filter(|gap| gap.from < gap.to)
+        .collect();
+    for gap in &res {
+        log::warn!(
+            "gap {} {}: {} - {}, missing {}",
+            pair,
+            interval,
+            gap.from,
+            gap.to,
+            gap.missing(interval)
+        );
+    }
+    Ok(res)
 }
 
-async fn import(
+/// Loads klines for the gaps and sends them to save, returns count of sent lines
+pub async fn fill_gaps(w_data: &WorkingData, gaps: &[Gap]) -> Result<usize, Box<dyn Error>> {
+    let mut res = 0;
+    for gap in gaps {
+        log::info!("Fill gap {}: {} - {}", w_data.pair, gap.from, gap.to);
+        let mut from = gap.from;
+        while from < gap.to {
+            let klines = load(w_data, from).await?;
+            let next = klines.iter().map(|l| l.open_time()).max();
+            for line in klines.into_iter().filter(|l| l.open_time() < gap.to) {
+                w_data.sender.send(line).await?;
+                res += 1;
+            }
+            match next {
+                Some(next) if next >= from => from = w_data.interval.next_open(next),
+                _ => match skip_empty(w_data, from) {
+                    Some(next) => from = next,
+                    None => break,
+                },
+            }
+        }
+    }
+    Ok(res)
+}
+
+/// Imports klines until the last closed one, returns the last imported open time
+pub async fn catch_up(
     w_data: &WorkingData,
     from: DateTime<Utc>,
 ) -> Result<DateTime<Utc>, Box<dyn Error>> {
+    let mut last = from;
+    while w_data.interval.next_open(last) < clock::now() {
+        let next = import(w_data, last).await?;
+        if next <= last {
+            break;
+        }
+        last = next;
+    }
+    Ok(last)
+}
+
+async fn load(w_data: &WorkingData, from: DateTime<Utc>) -> Result<Vec<KLine>, Box<dyn Error>> {
     {
         log::info!("wait for import");
         let wait = w_data.limiter.lock().await;
-        wait.wait().await?;
+        wait.wait(w_data.loader.weight()).await?;
         log::info!("let's go");
     }
     log::info!(
@@ -143,13 +468,29 @@ async fn import(
 
     let klines = w_data
         .loader
-        .retrieve(w_data.pair.as_str(), w_data.interval.as_str(), from)
+        .retrieve(w_data.pair.as_str(), w_data.interval, from)
         .await?;
+    if let Some(used) = w_data.loader.used_weight() {
+        w_data.limiter.lock().await.update(used);
+    }
     klines.iter().for_each(|f| {
         log::trace!("{}", f.to_str());
         // w_data.saver.save(f).await;
     });
     log::info!("got {} lines", klines.len());
+    Ok(klines)
+}
+
+async fn import(
+    w_data: &WorkingData,
+    from: DateTime<Utc>,
+) -> Result<DateTime<Utc>, Box<dyn Error>> {
+    let klines = load(w_data, from).await?;
+    if klines.is_empty() {
+        if let Some(next) = skip_empty(w_data, from) {
+            return Ok(next);
+        }
+    }
     let mut res = from;
     for line in klines {
         if res < line.open_time() {
@@ -161,23 +502,617 @@ async fn import(
     Ok(res)
 }
 
+// where to go on after a window without klines, None if there is nothing to skip
+fn skip_empty(w_data: &WorkingData, from: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let next = w_data.loader.next_window(w_data.interval, from)?;
+    if next <= from || next > clock::now() {
+        return None;
+    }
+    log::debug!("no {} klines in {} - {}", w_data.pair, from, next);
+    Some(next)
+}
+
 pub async fn saver_start(
     db: Box<dyn DBSaver + Send + Sync>,
     receiver: &mut Receiver<KLine>,
+    batch_size: usize,
+    batch_wait: Duration,
 ) -> Result<(), String> {
     log::info!("start db saver loop");
-    loop {
-        let line = receiver.recv().await;
-        log::trace!("got line");
-        match line {
-            Some(line) => db
-                .save(&line)
-                .await
-                .map(|_v| ())
-                .map_err(|err| format!("save err: {}", err))?,
+    let mut closed = false;
+    while !closed {
+        let mut batch = match receiver.recv().await {
+            Some(line) => vec![line],
             None => break,
+        };
+        let deadline = tokio::time::Instant::now() + batch_wait;
+        while batch.len() < batch_size {
+            match tokio::time::timeout_at(deadline, receiver.recv()).await {
+                Ok(Some(line)) => batch.push(line),
+                Ok(None) => {
+                    closed = true;
+                    break;
+                }
+                Err(_) => break,
+            }
+        }
+        log::trace!("got {} lines", batch.len());
+        let (batch, rejected) = validate::split(batch);
+        if !rejected.is_empty() {
+            for r in &rejected {
+                log::warn!(
+                    "quarantine {} {} {}: {}",
+                    r.line.pair,
+                    r.line.interval,
+                    r.line.open_time(),
+                    r.reason
+                );
+                metrics::KLINES_REJECTED
+                    .with_label_values(&[&r.line.market, &r.line.pair, &r.line.interval, r.rule])
+                    .inc();
+            }
+            db.quarantine(&rejected)
+                .await
+                .map_err(|err| format!("quarantine err: {}", err))?;
+        }
+        if batch.is_empty() {
+            continue;
+        }
+        db.save_batch(&batch)
+            .await
+            .map_err(|err| format!("save err: {}", err))?;
+        for line in &batch {
+            metrics::KLINES_SAVED
+                .with_label_values(&[&line.market, &line.pair, &line.interval])
+                .inc();
         }
+        log::debug!("saved {} lines", batch.len());
     }
     log::info!("exit save loop");
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::test_kline;
+    use async_trait::async_trait;
+    use std::sync::Arc;
+
+    struct TestSaver {
+        batches: Arc<std::sync::Mutex<Vec<usize>>>,
+        quarantined: Arc<std::sync::Mutex<Vec<&'static str>>>,
+        last: Option<DateTime<Utc>>,
+    }
+
+    #[async_trait]
+    impl DBSaver for TestSaver {
+        async fn live(&self) -> Result<String, Box<dyn Error>> {
+            Ok("1".to_string())
+        }
+        async fn get_last_time(
+            &self,
+            _market: &str,
+            _pair: &str,
+            _interval: &str,
+        ) -> Result<Option<DateTime<Utc>>, Box<dyn Error>> {
+            Ok(self.last)
+        }
+        async fn get_gaps(
+            &self,
+            _market: &str,
+            _pair: &str,
+            _interval: &str,
+            _step: chrono::Duration,
+        ) -> Result<Vec<Gap>, Box<dyn Error>> {
+            Ok(vec![])
+        }
+        async fn save(&self, _data: &KLine) -> Result<bool, Box<dyn Error>> {
+            Err("unexpected".into())
+        }
+        async fn save_batch(&self, data: &[KLine]) -> Result<bool, Box<dyn Error>> {
+            self.batches.lock().unwrap().push(data.len());
+            Ok(true)
+        }
+        async fn quarantine(&self, data: &[data::Rejected]) -> Result<bool, Box<dyn Error>> {
+            let mut res = self.quarantined.lock().unwrap();
+            data.iter().for_each(|r| res.push(r.rule));
+            Ok(true)
+        }
+    }
+
+    struct TestLoader {
+        first: Option<DateTime<Utc>>,
+        klines: Vec<KLine>,
+    }
+
+    #[async_trait]
+    impl Loader for TestLoader {
+        async fn live(&self) -> Result<String, Box<dyn Error>> {
+            Ok("{}".to_string())
+        }
+        async fn retrieve(
+            &self,
+            _pair: &str,
+            _interval: Interval,
+            from: DateTime<Utc>,
+        ) -> Result<Vec<KLine>, Box<dyn Error>> {
+            Ok(self
+                .klines
+                .iter()
+                .filter(|l| l.open_time() >= from)
+                .take(2)
+                .cloned()
+                .collect())
+        }
+        async fn first_time(
+            &self,
+            _pair: &str,
+            _interval: Interval,
+        ) -> Result<Option<DateTime<Utc>>, Box<dyn Error>> {
+            Ok(self.first)
+        }
+    }
+
+    // returns klines of a 10s window per call
+    struct WindowLoader {
+        klines: Vec<KLine>,
+    }
+
+    #[async_trait]
+    impl Loader for WindowLoader {
+        async fn live(&self) -> Result<String, Box<dyn Error>> {
+            Ok("{}".to_string())
+        }
+        async fn retrieve(
+            &self,
+            _pair: &str,
+            interval: Interval,
+            from: DateTime<Utc>,
+        ) -> Result<Vec<KLine>, Box<dyn Error>> {
+            let end = self.next_window(interval, from).unwrap();
+            Ok(self
+                .klines
+                .iter()
+                .filter(|l| l.open_time() >= from && l.open_time() < end)
+                .cloned()
+                .collect())
+        }
+        fn next_window(&self, interval: Interval, from: DateTime<Utc>) -> Option<DateTime<Utc>> {
+            Some(interval.after(from, 10))
+        }
+    }
+
+    // returns nothing or fails like a delisted pair
+    struct HaltedLoader {
+        fail: bool,
+        status: &'static str,
+    }
+
+    #[async_trait]
+    impl Loader for HaltedLoader {
+        async fn live(&self) -> Result<String, Box<dyn Error>> {
+            Ok("{}".to_string())
+        }
+        async fn retrieve(
+            &self,
+            _pair: &str,
+            _interval: Interval,
+            _from: DateTime<Utc>,
+        ) -> Result<Vec<KLine>, Box<dyn Error>> {
+            match self.fail {
+                true => Err("Invalid symbol".into()),
+                false => Ok(vec![]),
+            }
+        }
+        async fn status(&self, _pair: &str) -> Result<Option<String>, Box<dyn Error>> {
+            Ok(Some(self.status.to_string()))
+        }
+    }
+
+    struct TimeLoader {}
+
+    #[async_trait]
+    impl Loader for TimeLoader {
+        async fn live(&self) -> Result<String, Box<dyn Error>> {
+            Ok("{}".to_string())
+        }
+        async fn retrieve(
+            &self,
+            _pair: &str,
+            _interval: Interval,
+            _from: DateTime<Utc>,
+        ) -> Result<Vec<KLine>, Box<dyn Error>> {
+            Ok(vec![])
+        }
+        async fn server_time(&self) -> Result<Option<DateTime<Utc>>, Box<dyn Error>> {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            Ok(Some(Utc::now()))
+        }
+    }
+
+    struct StatusSaver {
+        statuses: Arc<std::sync::Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl DBSaver for StatusSaver {
+        async fn live(&self) -> Result<String, Box<dyn Error>> {
+            Ok("1".to_string())
+        }
+        async fn get_last_time(
+            &self,
+            _market: &str,
+            _pair: &str,
+            _interval: &str,
+        ) -> Result<Option<DateTime<Utc>>, Box<dyn Error>> {
+            Ok(None)
+        }
+        async fn get_gaps(
+            &self,
+            _market: &str,
+            _pair: &str,
+            _interval: &str,
+            _step: chrono::Duration,
+        ) -> Result<Vec<Gap>, Box<dyn Error>> {
+            Ok(vec![])
+        }
+        async fn save(&self, _data: &KLine) -> Result<bool, Box<dyn Error>> {
+            Err("unexpected".into())
+        }
+        async fn save_status(&self, data: &SymbolStatus) -> Result<bool, Box<dyn Error>> {
+            let res = format!("{} {} {}", data.market, data.pair, data.status);
+            self.statuses.lock().unwrap().push(res);
+            Ok(true)
+        }
+    }
+
+    fn config(since: Option<&str>, pair_since: &str, since_listing: bool) -> Config {
+        Config {
+            pairs: vec!["olia".to_string()],
+            interval: "1h".parse().unwrap(),
+            market: "spot".to_string(),
+            urls: vec![],
+            db_url: "".to_string(),
+            since: since.map(|s| parse_time(s).unwrap()),
+            pair_since: parse_pair_since(pair_since).unwrap(),
+            since_listing,
+        }
+    }
+
+    fn time(value: &str) -> DateTime<Utc> {
+        parse_time(value).unwrap()
+    }
+
+    #[test]
+    fn parses_time() {
+        assert_eq!(time("2017-08-17").timestamp(), 1502928000);
+        assert_eq!(time("2017-08-17T04:00:00Z").timestamp(), 1502942400);
+        assert_eq!(time("2017-08-17T06:00:00+02:00").timestamp(), 1502942400);
+        assert!(parse_time("2017-13-17").is_err());
+        assert!(parse_time("olia").is_err());
+    }
+
+    #[test]
+    fn parses_pair_since() {
+        let res = parse_pair_since("BTCUSDT=2017-08-17, ETHUSDT=2018-01-01T00:00:00Z").unwrap();
+        assert_eq!(res.len(), 2);
+        assert_eq!(res["BTCUSDT"], time("2017-08-17"));
+        assert_eq!(res["ETHUSDT"], time("2018-01-01"));
+        assert!(parse_pair_since("").unwrap().is_empty());
+        assert!(parse_pair_since("BTCUSDT").is_err());
+        assert!(parse_pair_since("BTCUSDT=olia").is_err());
+    }
+
+    #[tokio::test]
+    async fn start_time() {
+        let empty = || TestSaver {
+            batches: Arc::new(std::sync::Mutex::new(Vec::new())),
+            quarantined: Arc::new(std::sync::Mutex::new(Vec::new())),
+            last: None,
+        };
+        let loader = TestLoader {
+            first: Some(time("2019-01-01")),
+            klines: vec![],
+        };
+        for (db, since, pair_since, listing, expected) in [
+            (empty(), None, "", false, "1970-01-01"),
+            (empty(), Some("2018-01-01"), "", false, "2018-01-01"),
+            (
+                empty(),
+                Some("2018-01-01"),
+                "olia=2017-01-01",
+                true,
+                "2017-01-01",
+            ),
+            (
+                empty(),
+                Some("2018-01-01"),
+                "other=2017-01-01",
+                true,
+                "2019-01-01",
+            ),
+            (empty(), Some("2020-01-01"), "", true, "2020-01-01"),
+            (empty(), None, "", true, "2019-01-01"),
+            (
+                TestSaver {
+                    batches: Arc::new(std::sync::Mutex::new(Vec::new())),
+                    quarantined: Arc::new(std::sync::Mutex::new(Vec::new())),
+                    last: Some(time("2021-01-01")),
+                },
+                Some("2018-01-01"),
+                "olia=2017-01-01",
+                true,
+                "2021-01-01",
+            ),
+        ] {
+            let cfg = config(since, pair_since, listing);
+            let res = get_start_time(&db, &loader, &cfg, "olia").await.unwrap();
+            assert_eq!(
+                res,
+                time(expected),
+                "{:?} {} {}",
+                since,
+                pair_since,
+                listing
+            );
+        }
+    }
+
+    struct TestLimiter {}
+
+    #[async_trait]
+    impl Limiter for TestLimiter {
+        async fn wait(&self, _weight: u32) -> Result<bool, Box<dyn Error>> {
+            Ok(true)
+        }
+    }
+
+    #[tokio::test]
+    async fn fills_gaps() {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        let limiter: Box<dyn Limiter> = Box::new(TestLimiter {});
+        let w_data = WorkingData {
+            pair: "olia".to_string(),
+            market: "spot".to_string(),
+            interval: "1s".parse().unwrap(),
+            start_from: Utc::now(),
+            gaps: vec![],
+            loader: Box::new(TestLoader {
+                first: None,
+                klines: [0, 1, 3, 4, 5, 6, 8, 9]
+                    .iter()
+                    .map(|i| test_kline(i * 1000, "1s"))
+                    .collect(),
+            }),
+            limiter: std::sync::Arc::new(Mutex::new(limiter)),
+            sender: tx,
+            status_db: None,
+        };
+        let gaps = vec![
+            Gap {
+                from: Utc.timestamp_opt(1, 0).unwrap(),
+                to: Utc.timestamp_opt(6, 0).unwrap(),
+            },
+            Gap {
+                from: Utc.timestamp_opt(7, 0).unwrap(),
+                to: Utc.timestamp_opt(8, 0).unwrap(),
+            },
+            Gap {
+                from: Utc.timestamp_opt(10, 0).unwrap(),
+                to: Utc.timestamp_opt(12, 0).unwrap(),
+            },
+        ];
+        let res = fill_gaps(&w_data, &gaps).await.unwrap();
+        drop(w_data);
+        let mut sent = vec![];
+        while let Some(line) = rx.recv().await {
+            sent.push(line.open_time / 1000);
+        }
+        assert_eq!(res, 4);
+        assert_eq!(sent, vec![1, 3, 4, 5]);
+    }
+
+    #[tokio::test]
+    async fn catches_up() {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        let limiter: Box<dyn Limiter> = Box::new(TestLimiter {});
+        let w_data = WorkingData {
+            pair: "olia".to_string(),
+            market: "spot".to_string(),
+            interval: "1s".parse().unwrap(),
+            start_from: Utc::now(),
+            gaps: vec![],
+            loader: Box::new(TestLoader {
+                first: None,
+                klines: [0, 1, 2, 3]
+                    .iter()
+                    .map(|i| test_kline(i * 1000, "1s"))
+                    .collect(),
+            }),
+            limiter: std::sync::Arc::new(Mutex::new(limiter)),
+            sender: tx,
+            status_db: None,
+        };
+        let res = catch_up(&w_data, Utc.timestamp_opt(1, 0).unwrap())
+            .await
+            .unwrap();
+        let now = Utc::now();
+        assert_eq!(catch_up(&w_data, now).await.unwrap(), now);
+        drop(w_data);
+        let mut sent = vec![];
+        while let Some(line) = rx.recv().await {
+            sent.push(line.open_time / 1000);
+        }
+        assert_eq!(res, Utc.timestamp_opt(3, 0).unwrap());
+        assert_eq!(sent, vec![1, 2, 2, 3, 3]);
+    }
+
+    #[tokio::test]
+    async fn skips_empty_windows() {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        let limiter: Box<dyn Limiter> = Box::new(TestLimiter {});
+        let w_data = WorkingData {
+            pair: "olia".to_string(),
+            market: "spot".to_string(),
+            interval: "1s".parse().unwrap(),
+            start_from: Utc::now(),
+            gaps: vec![],
+            loader: Box::new(WindowLoader {
+                klines: [35, 36, 72]
+                    .iter()
+                    .map(|i| test_kline(i * 1000, "1s"))
+                    .collect(),
+            }),
+            limiter: std::sync::Arc::new(Mutex::new(limiter)),
+            sender: tx,
+            status_db: None,
+        };
+        let res = catch_up(&w_data, Utc.timestamp_opt(0, 0).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(res, Utc.timestamp_opt(36, 0).unwrap());
+        let gaps = vec![Gap {
+            from: Utc.timestamp_opt(37, 0).unwrap(),
+            to: Utc.timestamp_opt(80, 0).unwrap(),
+        }];
+        assert_eq!(fill_gaps(&w_data, &gaps).await.unwrap(), 1);
+        drop(w_data);
+        let mut sent = vec![];
+        while let Some(line) = rx.recv().await {
+            sent.push(line.open_time / 1000);
+        }
+        assert_eq!(sent, vec![35, 36, 36, 72]);
+    }
+
+    #[tokio::test]
+    async fn stops_halted_pair() {
+        for (fail, status) in [(false, "BREAK"), (true, "DELISTED")] {
+            let (tx, _rx) = tokio::sync::mpsc::channel(100);
+            let limiter: Box<dyn Limiter> = Box::new(TestLimiter {});
+            let statuses = Arc::new(std::sync::Mutex::new(Vec::new()));
+            let w_data = WorkingData {
+                pair: "olia".to_string(),
+                market: "spot".to_string(),
+                interval: "1m".parse().unwrap(),
+                start_from: Utc::now() - chrono::Duration::hours(1),
+                gaps: vec![],
+                loader: Box::new(HaltedLoader { fail, status }),
+                limiter: std::sync::Arc::new(Mutex::new(limiter)),
+                sender: tx,
+                status_db: Some(Arc::new(StatusSaver {
+                    statuses: statuses.clone(),
+                })),
+            };
+            let (tx_close, rx_close) = watch::channel(0);
+            let (tx_exit, mut rx_exit) = tokio::sync::mpsc::unbounded_channel();
+            let close = async move {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                drop(tx_close);
+            };
+            let run = run_exit_indicator(w_data, rx_close, tx_exit);
+            let (res, _) =
+                tokio::time::timeout(Duration::from_secs(5), async { tokio::join!(run, close) })
+                    .await
+                    .unwrap();
+            assert!(res.is_ok(), "{}", status);
+            assert!(rx_exit.try_recv().is_err(), "{}", status);
+            assert_eq!(
+                *statuses.lock().unwrap(),
+                vec![format!("spot olia {}", status)]
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn syncs_clock() {
+        let max_skew = chrono::Duration::seconds(1);
+        let offset = sync_clock(&TimeLoader {}, max_skew).await.unwrap().unwrap();
+        assert!(
+            offset.abs() < chrono::Duration::milliseconds(100),
+            "{}",
+            offset
+        );
+        assert_eq!(clock::offset(), offset);
+        let loader = TestLoader {
+            first: None,
+            klines: vec![],
+        };
+        assert_eq!(sync_clock(&loader, max_skew).await.unwrap(), None);
+        // the offset is global, do not leak it to other tests
+        clock::set_offset(chrono::Duration::zero());
+    }
+
+    #[tokio::test]
+    async fn saver_splits_by_size() {
+        let batches = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        for i in 0..7 {
+            tx.send(test_kline(i * 1000, "1s")).await.unwrap();
+        }
+        drop(tx);
+        let db = TestSaver {
+            batches: batches.clone(),
+            quarantined: Arc::new(std::sync::Mutex::new(Vec::new())),
+            last: None,
+        };
+        saver_start(Box::new(db), &mut rx, 3, Duration::from_secs(10))
+            .await
+            .unwrap();
+        assert_eq!(*batches.lock().unwrap(), vec![3, 3, 1]);
+    }
+
+    // the paused clock jumps to the batch deadline once the saver waits
+    #[tokio::test(start_paused = true)]
+    async fn saver_flushes_by_time() {
+        let batches = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        let db = TestSaver {
+            batches: batches.clone(),
+            quarantined: Arc::new(std::sync::Mutex::new(Vec::new())),
+            last: None,
+        };
+        let saver = tokio::spawn(async move {
+            saver_start(Box::new(db), &mut rx, 100, Duration::from_millis(50)).await
+        });
+        tx.send(test_kline(0, "1s")).await.unwrap();
+        tx.send(test_kline(1000, "1s")).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(*batches.lock().unwrap(), vec![2]);
+        tx.send(test_kline(2000, "1s")).await.unwrap();
+        drop(tx);
+        saver.await.unwrap().unwrap();
+        assert_eq!(*batches.lock().unwrap(), vec![2, 1]);
+    }
+
+    #[tokio::test]
+    async fn saver_quarantines_invalid() {
+        let batches = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let quarantined = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        tx.send(test_kline(0, "1s")).await.unwrap();
+        let mut bad = test_kline(1000, "1s");
+        bad.high_price = 1.2;
+        tx.send(bad).await.unwrap();
+        let mut bad = test_kline(2000, "1s");
+        bad.close_time = 2000;
+        tx.send(bad).await.unwrap();
+        drop(tx);
+        let db = TestSaver {
+            batches: batches.clone(),
+            quarantined: quarantined.clone(),
+            last: None,
+        };
+        saver_start(Box::new(db), &mut rx, 100, Duration::from_secs(10))
+            .await
+            .unwrap();
+        assert_eq!(*batches.lock().unwrap(), vec![1]);
+        assert_eq!(*quarantined.lock().unwrap(), vec!["high", "close_time"]);
+        assert_eq!(
+            metrics::KLINES_REJECTED
+                .with_label_values(&["spot", "olia", "1s", "high"])
+                .get(),
+            1
+        );
+    }
+}
@@ -1,29 +1,112 @@
-use std::{error::Error, time::Duration, num::NonZeroU32};
+use std::{error::Error, time::Duration, num::NonZeroU32, sync::Mutex};
 
 use async_trait::async_trait;
-use cprices::data::Limiter;
+use chrono::{DateTime, Utc};
+use cprices::{clock, data::Limiter};
 use governor::{state::{NotKeyed, InMemoryState}, clock::{QuantaClock}};
 
+// part of the exchange weight limit to use, the rest is left for other clients of the IP
+const USE_PERCENT: u32 = 90;
+
 pub struct RateLimiter {
     governor:governor::RateLimiter<NotKeyed, InMemoryState, QuantaClock, >,
-    jitter: governor::Jitter, 
+    jitter: governor::Jitter,
+    /// exchange weight limit per minute to check the reported used weight against
+    weight_limit: Option<u32>,
+    /// minute and the weight used in it as reported by the exchange
+    used: Mutex<(i64, u32)>,
 }
 
 impl RateLimiter {
-    pub fn new() -> Result<RateLimiter, Box<dyn Error>> {
+    pub fn new(per_minute: u32) -> Result<RateLimiter, Box<dyn Error>> {
         let governor = governor::RateLimiter::direct(
-            governor::Quota::per_minute(NonZeroU32::new(60).expect("Governor rate is 0")));
+            governor::Quota::per_minute(NonZeroU32::new(per_minute).ok_or("Governor rate is 0")?));
         let jitter = governor::Jitter::new(Duration::ZERO, Duration::from_secs(3));
-        Ok(RateLimiter {governor, jitter})
+        Ok(RateLimiter {governor, jitter, weight_limit: None, used: Mutex::new((0, 0))})
+    }
+
+    /// allows `count` requests in a `period` without a burst above it
+    pub fn per_period(count: u32, period: Duration) -> Result<RateLimiter, Box<dyn Error>> {
+        let count = NonZeroU32::new(count).ok_or("Governor rate is 0")?;
+        let quota = governor::Quota::with_period(period / count.get()).ok_or("Governor period is 0")?.allow_burst(count);
+        let governor = governor::RateLimiter::direct(quota);
+        let jitter = governor::Jitter::new(Duration::ZERO, Duration::from_millis(100));
+        Ok(RateLimiter {governor, jitter, weight_limit: None, used: Mutex::new((0, 0))})
+    }
+
+    /// allows most of the exchange's `limit` of request weight per minute,
+    /// waits for the next minute if the used weight reported by the exchange comes close to the limit
+    pub fn weighted(limit: u32) -> Result<RateLimiter, Box<dyn Error>> {
+        let per_minute = NonZeroU32::new(limit * USE_PERCENT / 100).ok_or("Governor rate is 0")?;
+        let burst = NonZeroU32::new((per_minute.get() / 10).max(10)).ok_or("Governor burst is 0")?;
+        let quota = governor::Quota::per_minute(per_minute).allow_burst(burst);
+        let governor = governor::RateLimiter::direct(quota);
+        let jitter = governor::Jitter::new(Duration::ZERO, Duration::from_millis(100));
+        Ok(RateLimiter {governor, jitter, weight_limit: Some(limit), used: Mutex::new((0, 0))})
+    }
+
+    // time to wait for the exchange's minute to end
+    fn pause(&self, weight: u32, now: DateTime<Utc>) -> Option<Duration> {
+        let limit = self.weight_limit?;
+        let (minute, used) = *self.used.lock().unwrap();
+        if minute != now.timestamp() / 60 || used + weight <= limit * USE_PERCENT / 100 {
+            return None;
+        }
+        Some(Duration::from_millis((60_000 - now.timestamp_millis() % 60_000) as u64))
     }
 }
 
 #[async_trait]
 impl Limiter for RateLimiter {
-    async fn wait(&self) -> std::result::Result<bool, Box<dyn Error>> {
-        log::debug!("wait until_ready_with_jitter");
-        self.governor.until_ready_with_jitter(self.jitter).await;
+    async fn wait(&self, weight: u32) -> std::result::Result<bool, Box<dyn Error>> {
+        log::debug!("wait until_n_ready_with_jitter {}", weight);
+        let weight = NonZeroU32::new(weight.max(1)).unwrap();
+        self.governor.until_n_ready_with_jitter(weight, self.jitter).await
+            .map_err(|e| format!("weight {} is over the limit: {}", weight, e))?;
+        if let Some(pause) = self.pause(weight.get(), clock::now()) {
+            log::warn!("used weight is close to the limit, wait {:?}", pause);
+            tokio::time::sleep(pause).await;
+        }
         log::debug!("allowed");
         Ok(true)
     }
+
+    fn update(&self, used: u32) {
+        log::debug!("used weight {}", used);
+        *self.used.lock().unwrap() = (clock::now().timestamp() / 60, used);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn pauses_close_to_limit() {
+        let limiter = RateLimiter::weighted(6000).unwrap();
+        let now = Utc.timestamp_millis_opt(1672531215000).unwrap();
+        assert_eq!(limiter.pause(2, now), None);
+        *limiter.used.lock().unwrap() = (now.timestamp() / 60, 5398);
+        assert_eq!(limiter.pause(2, now), None);
+        *limiter.used.lock().unwrap() = (now.timestamp() / 60, 5399);
+        assert_eq!(limiter.pause(2, now), Some(Duration::from_secs(45)));
+        // reported in the previous minute
+        *limiter.used.lock().unwrap() = (now.timestamp() / 60 - 1, 5999);
+        assert_eq!(limiter.pause(2, now), None);
+    }
+
+    #[test]
+    fn no_pause_without_limit() {
+        let limiter = RateLimiter::new(60).unwrap();
+        limiter.update(100000);
+        assert_eq!(limiter.pause(2, Utc::now()), None);
+    }
+
+    #[tokio::test]
+    async fn fails_on_weight_over_burst() {
+        let limiter = RateLimiter::per_period(2, Duration::from_secs(1)).unwrap();
+        assert!(limiter.wait(1).await.is_ok());
+        assert!(limiter.wait(3).await.is_err());
+    }
 }
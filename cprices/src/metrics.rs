@@ -0,0 +1,50 @@
+use once_cell::sync::Lazy;
+use prometheus::{Gauge, IntCounterVec, Opts, Registry, TextEncoder};
+
+pub static REGISTRY: Lazy<Registry> = Lazy::new(Registry::new);
+
+pub static KLINES_SAVED: Lazy<IntCounterVec> = Lazy::new(|| {
+    counter(
+        "cprices_klines_saved_total",
+        "KLines passed to the DB",
+        &["market", "pair", "interval"],
+    )
+});
+
+pub static KLINES_REJECTED: Lazy<IntCounterVec> = Lazy::new(|| {
+    counter(
+        "cprices_klines_rejected_total",
+        "KLines failed the sanity checks and moved to quarantine",
+        &["market", "pair", "interval", "rule"],
+    )
+});
+
+pub static CLOCK_OFFSET: Lazy<Gauge> = Lazy::new(|| {
+    let res = Gauge::new(
+        "cprices_clock_offset_seconds",
+        "Exchange server time minus the local time as last measured",
+    )
+    .expect("metric");
+    REGISTRY
+        .register(Box::new(res.clone()))
+        .expect("metric register");
+    res
+});
+
+fn counter(name: &str, help: &str, labels: &[&str]) -> IntCounterVec {
+    let res = IntCounterVec::new(Opts::new(name, help), labels).expect("metric");
+    REGISTRY
+        .register(Box::new(res.clone()))
+        .expect("metric register");
+    res
+}
+
+/// Returns all metrics in the prometheus text format
+pub fn gather() -> String {
+    TextEncoder::new()
+        .encode_to_string(&REGISTRY.gather())
+        .unwrap_or_else(|e| {
+            log::error!("metrics encode: {e}");
+            String::new()
+        })
+}
@@ -0,0 +1,300 @@
+use clap::{value_parser, Arg, ArgMatches, Command};
+use deadpool_postgres::Client;
+use std::error::Error;
+
+use crate::postgresql::PostgresClient;
+
+// keeps the table layout of golang-migrate, so both tools can be used on the same DB
+const CREATE_TABLE: &str =
+    "CREATE TABLE IF NOT EXISTS schema_migrations (version bigint NOT NULL PRIMARY KEY, dirty boolean NOT NULL)";
+const LOCK_ID: i64 = 7_201_301_455;
+
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub up: &'static str,
+    pub down: &'static str,
+}
+
+macro_rules! migration {
+    ($version:expr, $name:literal) => {
+        Migration {
+            version: $version,
+            name: $name,
+            up: include_str!(concat!("../../sql/", $name, ".up.sql")),
+            down: include_str!(concat!("../../sql/", $name, ".down.sql")),
+        }
+    };
+}
+
+pub const MIGRATIONS: &[Migration] = &[
+    migration!(1, "000001_tables"),
+    migration!(2, "000002_interval"),
+    migration!(3, "000003_volumes"),
+    migration!(4, "000004_closed"),
+    migration!(5, "000005_quarantine"),
+    migration!(6, "000006_market"),
+    migration!(7, "000007_symbol_status"),
+    migration!(8, "000008_pair_length"),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchemaVersion {
+    pub version: Option<i64>,
+    pub dirty: bool,
+}
+
+pub fn latest_version() -> i64 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+pub fn command() -> Command {
+    Command::new("migrate")
+        .about("Manages DB schema migrations embedded in the importer")
+        .subcommand_required(true)
+        .subcommand(Command::new("up").about("Applies all pending migrations"))
+        .subcommand(
+            Command::new("down")
+                .about("Rolls back applied migrations")
+                .arg(
+                    Arg::new("steps")
+                        .value_name("N")
+                        .help("Count of migrations to roll back")
+                        .value_parser(value_parser!(usize))
+                        .default_value("1"),
+                ),
+        )
+        .subcommand(Command::new("status").about("Shows DB schema version"))
+        .subcommand(
+            Command::new("force")
+                .about("Sets version without running migrations, -1 clears it")
+                .arg(
+                    Arg::new("version")
+                        .value_name("VERSION")
+                        .value_parser(value_parser!(i64))
+                        .allow_negative_numbers(true)
+                        .required(true),
+                ),
+        )
+}
+
+pub async fn run_command(db: &PostgresClient, args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let mut client = db.client().await?;
+    if let Some(("status", _)) = args.subcommand() {
+        return status(&client).await;
+    }
+    log::debug!("wait for migration lock");
+    client
+        .execute("SELECT pg_advisory_lock($1)", &[&LOCK_ID])
+        .await?;
+    client.batch_execute(CREATE_TABLE).await?;
+    let res = match args.subcommand() {
+        Some(("up", _)) => up(&mut client).await,
+        Some(("down", sub)) => {
+            let steps = *sub.get_one::<usize>("steps").expect("no steps");
+            down(&mut client, steps).await
+        }
+        Some(("force", sub)) => {
+            let version = *sub.get_one::<i64>("version").expect("no version");
+            let version = if version < 0 { None } else { Some(version) };
+            set_version(&mut client, version, false).await
+        }
+        _ => Err("unknown migrate command".into()),
+    };
+    if res.is_err() {
+        // migrations run in a transaction, the failed one leaves it aborted
+        if let Err(err) = client.batch_execute("ROLLBACK").await {
+            log::warn!("rollback: {}", err);
+        }
+    }
+    let unlock = client
+        .execute("SELECT pg_advisory_unlock($1)", &[&LOCK_ID])
+        .await;
+    if let Err(err) = &unlock {
+        log::error!("release migration lock: {}", err);
+    }
+    res.and(unlock.map(|_| ()).map_err(|e| e.into()))
+}
+
+/// Fails if the DB schema is not migrated to the version this importer was built for
+pub async fn check(db: &PostgresClient) -> Result<(), Box<dyn Error>> {
+    let client = db.client().await?;
+    let current = get_version(&client).await?;
+    let expected = latest_version();
+    if current.dirty {
+        return Err(format!(
+            "DB schema is dirty at version {}, fix it and run `importer migrate force`",
+            fmt_version(current.version)
+        )
+        .into());
+    }
+    if current.version != Some(expected) {
+        return Err(format!(
+            "DB schema version {}, expected {}, run `importer migrate up`",
+            fmt_version(current.version),
+            expected
+        )
+        .into());
+    }
+    Ok(())
+}
+
+async fn up(client: &mut Client) -> Result<(), Box<dyn Error>> {
+    let current = get_version(client).await?;
+    if current.dirty {
+        return Err(format!(
+            "DB schema is dirty at version {}",
+            fmt_version(current.version)
+        )
+        .into());
+    }
+    let pending: Vec<&Migration> = MIGRATIONS
+        .iter()
+        .filter(|m| Some(m.version) > current.version)
+        .collect();
+    if pending.is_empty() {
+        log::info!("no change, version {}", fmt_version(current.version));
+    }
+    for m in pending {
+        log::info!("applying {}", m.name);
+        set_version(client, Some(m.version), true).await?;
+        client
+            .batch_execute(m.up)
+            .await
+            .map_err(|e| format!("migration {} up: {}", m.name, db_error(&e)))?;
+        set_version(client, Some(m.version), false).await?;
+    }
+    Ok(())
+}
+
+async fn down(client: &mut Client, steps: usize) -> Result<(), Box<dyn Error>> {
+    let current = get_version(client).await?;
+    if current.dirty {
+        return Err(format!(
+            "DB schema is dirty at version {}",
+            fmt_version(current.version)
+        )
+        .into());
+    }
+    let applied: Vec<&Migration> = MIGRATIONS
+        .iter()
+        .filter(|m| Some(m.version) <= current.version)
+        .collect();
+    for (i, m) in applied.iter().enumerate().rev().take(steps) {
+        log::info!("rolling back {}", m.name);
+        set_version(client, Some(m.version), true).await?;
+        client
+            .batch_execute(m.down)
+            .await
+            .map_err(|e| format!("migration {} down: {}", m.name, db_error(&e)))?;
+        let prev = i.checked_sub(1).map(|p| applied[p].version);
+        set_version(client, prev, false).await?;
+    }
+    Ok(())
+}
+
+async fn status(client: &Client) -> Result<(), Box<dyn Error>> {
+    let current = get_version(client).await?;
+    println!(
+        "version: {}{}",
+        fmt_version(current.version),
+        if current.dirty { " (dirty)" } else { "" }
+    );
+    println!("latest:  {}", latest_version());
+    for m in MIGRATIONS {
+        let state = if Some(m.version) <= current.version {
+            "applied"
+        } else {
+            "pending"
+        };
+        println!("{} {}", state, m.name);
+    }
+    Ok(())
+}
+
+async fn get_version(client: &Client) -> Result<SchemaVersion, Box<dyn Error>> {
+    let rows = client
+        .query("SELECT to_regclass('schema_migrations') IS NOT NULL", &[])
+        .await?;
+    let exists: bool = rows[0].get(0);
+    if !exists {
+        return Ok(SchemaVersion {
+            version: None,
+            dirty: false,
+        });
+    }
+    let rows = client
+        .query("SELECT version, dirty FROM schema_migrations LIMIT 1", &[])
+        .await?;
+    Ok(match rows.first() {
+        Some(row) => SchemaVersion {
+            version: Some(row.get(0)),
+            dirty: row.get(1),
+        },
+        None => SchemaVersion {
+            version: None,
+            dirty: false,
+        },
+    })
+}
+
+async fn set_version(
+    client: &mut Client,
+    version: Option<i64>,
+    dirty: bool,
+) -> Result<(), Box<dyn Error>> {
+    let tx = client.transaction().await?;
+    tx.execute("TRUNCATE schema_migrations", &[]).await?;
+    if let Some(v) = version {
+        tx.execute(
+            "INSERT INTO schema_migrations (version, dirty) VALUES ($1, $2)",
+            &[&v, &dirty],
+        )
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+// the server's message, tokio_postgres displays it as `db error` only
+fn db_error(err: &tokio_postgres::Error) -> String {
+    err.as_db_error()
+        .map_or_else(|| err.to_string(), |e| e.to_string())
+}
+
+fn fmt_version(version: Option<i64>) -> String {
+    version.map_or("nil".to_string(), |v| v.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions_ascending() {
+        let mut prev = 0;
+        for m in MIGRATIONS {
+            assert!(m.version > prev, "{}", m.name);
+            assert!(m.name.starts_with(&format!("{:06}_", m.version)));
+            assert!(!m.up.trim().is_empty());
+            assert!(!m.down.trim().is_empty());
+            prev = m.version;
+        }
+    }
+
+    #[test]
+    fn all_files_embedded() {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../sql");
+        let count = std::fs::read_dir(dir)
+            .unwrap()
+            .filter(|f| {
+                f.as_ref()
+                    .unwrap()
+                    .file_name()
+                    .to_string_lossy()
+                    .ends_with(".up.sql")
+            })
+            .count();
+        assert_eq!(count, MIGRATIONS.len());
+    }
+}
@@ -0,0 +1,229 @@
+use async_trait::async_trait;
+use chrono::{DateTime, TimeZone, Utc};
+use cprices::data::{KLine, Loader};
+use cprices::interval::Interval;
+use reqwest_middleware::ClientWithMiddleware;
+use serde::Deserialize;
+use std::error::Error;
+use std::time::Duration;
+
+use crate::endpoints::{http_client, Endpoints};
+
+pub const MARKET: &str = "okx";
+/// history candles allow 20 requests per 2 seconds
+pub const REQUESTS_PER_PERIOD: u32 = 20;
+pub const REQUESTS_PERIOD: Duration = Duration::from_secs(2);
+const URLS: &[&str] = &["https://www.okx.com"];
+const CANDLES_LIMIT: u32 = 100;
+
+/// Loads OKX history candles, pairs as instrument IDs e.g. BTC-USDT
+#[derive(Debug)]
+pub struct Okx {
+    endpoints: Endpoints,
+    client: ClientWithMiddleware,
+}
+
+impl Okx {
+    pub fn new(urls: &[String]) -> Result<Okx, Box<dyn Error>> {
+        Ok(Okx {
+            endpoints: Endpoints::new(urls, URLS)?,
+            client: http_client()?,
+        })
+    }
+}
+
+#[async_trait]
+impl Loader for Okx {
+    async fn live(&self) -> std::result::Result<String, Box<dyn Error>> {
+        let content = self
+            .endpoints
+            .get(&self.client, "api/v5/public/time")
+            .await?
+            .text()
+            .await?;
+        log::debug!("{} response: {}", self.endpoints.url(), content);
+        Ok(content)
+    }
+    async fn retrieve(
+        &self,
+        pair: &str,
+        interval: Interval,
+        from: DateTime<Utc>,
+    ) -> std::result::Result<Vec<KLine>, Box<dyn Error>> {
+        let bar = interval.okx()?;
+        // the page goes backwards from `after`, both bounds are exclusive
+        let end = interval.after(from, CANDLES_LIMIT);
+        let path = format!(
+            "api/v5/market/history-candles?instId={}&bar={}&after={}&before={}&limit={}",
+            pair,
+            bar,
+            end.timestamp_millis(),
+            from.timestamp_millis() - 1,
+            CANDLES_LIMIT
+        );
+        let resp = self
+            .endpoints
+            .get(&self.client, &path)
+            .await?
+            .json::<OkxResponse>()
+            .await?;
+        Ok(to_klines(&resp.data()?, pair, interval)?)
+    }
+    fn next_window(&self, interval: Interval, from: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        Some(interval.after(from, CANDLES_LIMIT))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct OkxResponse {
+    code: String,
+    #[serde(default)]
+    msg: String,
+    #[serde(default)]
+    data: Vec<Vec<String>>,
+}
+
+impl OkxResponse {
+    fn data(self) -> Result<Vec<Vec<String>>, String> {
+        if self.code != "0" {
+            return Err(format!("okx error {}: {}", self.code, self.msg));
+        }
+        Ok(self.data)
+    }
+}
+
+// rows come newest first
+fn to_klines(rows: &[Vec<String>], pair: &str, interval: Interval) -> Result<Vec<KLine>, String> {
+    let mut res = rows
+        .iter()
+        .map(|r| to_kline(r, pair, interval))
+        .collect::<Result<Vec<KLine>, String>>()?;
+    res.sort_by_key(|l| l.open_time);
+    Ok(res)
+}
+
+// [ts, o, h, l, c, vol, volCcy, volCcyQuote, confirm]
+fn to_kline(row: &[String], pair: &str, interval: Interval) -> Result<KLine, String> {
+    if row.len() < 9 {
+        return Err(format!("wrong okx candle {:?}", row));
+    }
+    let num = |i: usize| {
+        row[i]
+            .parse::<f64>()
+            .map_err(|e| format!("wrong okx candle value '{}': {}", row[i], e))
+    };
+    let open = row[0]
+        .parse::<i64>()
+        .ok()
+        .and_then(|t| Utc.timestamp_millis_opt(t).single())
+        .ok_or_else(|| format!("wrong okx candle time '{}'", row[0]))?;
+    Ok(KLine {
+        open_time: open.timestamp_millis(),
+        open_price: num(1)?,
+        high_price: num(2)?,
+        low_price: num(3)?,
+        close_price: num(4)?,
+        volume: num(5)?,
+        close_time: interval.close_time(open).timestamp_millis(),
+        quote_volume: num(7)?,
+        // not provided by okx
+        trades: 0,
+        taker_buy_volume: 0.0,
+        taker_buy_quote_volume: 0.0,
+        pair: pair.to_string(),
+        interval: interval.to_string(),
+        market: MARKET.to_string(),
+        is_closed: row[8] == "1",
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+
+    use crate::okx::{to_klines, OkxResponse};
+    use cprices::interval::Interval;
+
+    fn hour() -> Interval {
+        "1h".parse().unwrap()
+    }
+
+    // GET api/v5/market/history-candles?instId=BTC-USDT&bar=1H&after=1696125600000&before=1696114799999&limit=100
+    fn sample() -> &'static str {
+        r#"{"code":"0","msg":"","data":[
+        ["1696122000000","26985.4","27010.5","26950","27002.1","112.63","112.63","3040210.5","0"],
+        ["1696118400000","26962.5","26998.2","26931.6","26985.4","154.40","154.40","4165034.1","1"],
+        ["1696114800000","26967.2","26975","26902","26962.5","98.04","98.04","2642120.7","1"]]}"#
+    }
+
+    #[test]
+    fn deserialize_response() {
+        let resp: OkxResponse = serde_json::from_str(sample()).unwrap();
+        let rows = resp.data().unwrap();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0][0], "1696122000000");
+        assert_eq!(rows[0][8], "0");
+    }
+
+    #[test]
+    fn deserialize_error() {
+        let resp: OkxResponse = serde_json::from_str(
+            r#"{"code":"51001","msg":"Instrument ID does not exist","data":[]}"#,
+        )
+        .unwrap();
+        let err = resp.data().unwrap_err();
+        assert!(err.contains("51001"));
+        assert!(err.contains("Instrument ID does not exist"));
+    }
+
+    #[test]
+    fn maps_to_klines_ascending() {
+        let resp: OkxResponse = serde_json::from_str(sample()).unwrap();
+        let res = to_klines(&resp.data().unwrap(), "BTC-USDT", hour()).unwrap();
+        assert_eq!(res.len(), 3);
+        assert_eq!(res[0].open_time, 1696114800000);
+        assert_eq!(res[2].open_time, 1696122000000);
+        assert_eq!(res[2].close_time, 1696125599999);
+        assert_relative_eq!(res[2].open_price, 26985.4);
+        assert_relative_eq!(res[2].high_price, 27010.5);
+        assert_relative_eq!(res[2].low_price, 26950.0);
+        assert_relative_eq!(res[2].close_price, 27002.1);
+        assert_relative_eq!(res[2].volume, 112.63);
+        assert_relative_eq!(res[2].quote_volume, 3040210.5);
+        assert_eq!(res[2].pair, "BTC-USDT");
+        assert_eq!(res[2].interval, "1h");
+        assert_eq!(res[2].market, "okx");
+    }
+
+    #[test]
+    fn marks_closed_by_confirm() {
+        let resp: OkxResponse = serde_json::from_str(sample()).unwrap();
+        let res = to_klines(&resp.data().unwrap(), "BTC-USDT", hour()).unwrap();
+        assert!(res[0].is_closed);
+        assert!(res[1].is_closed);
+        assert!(!res[2].is_closed);
+    }
+
+    #[test]
+    fn fails_on_wrong_row() {
+        let rows = vec![vec!["1696122000000".to_string(), "1".to_string()]];
+        assert!(to_klines(&rows, "BTC-USDT", hour()).is_err());
+        let mut row: Vec<String> = serde_json::from_str::<OkxResponse>(sample())
+            .unwrap()
+            .data()
+            .unwrap()
+            .remove(0);
+        row[2] = "x".to_string();
+        assert!(to_klines(&[row], "BTC-USDT", hour()).is_err());
+    }
+
+    #[test]
+    fn closes_month_by_calendar() {
+        let row: Vec<String> =
+            serde_json::from_str(r#"["1675209600000","1","1","1","1","1","1","1","1"]"#).unwrap();
+        let res = to_klines(&[row], "BTC-USDT", "1M".parse().unwrap()).unwrap();
+        // 2023-02-28T23:59:59.999Z
+        assert_eq!(res[0].close_time, 1677628799999);
+        assert_eq!(res[0].interval, "1M");
+    }
+}
@@ -0,0 +1,24 @@
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+
+/// Serves prometheus metrics on /metrics
+pub async fn serve(port: u16) -> Result<(), hyper::Error> {
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    let make_svc = make_service_fn(|_conn| async { Ok::<_, Infallible>(service_fn(handle)) });
+    log::info!("metrics on http://{}/metrics", addr);
+    Server::bind(&addr).serve(make_svc).await
+}
+
+async fn handle(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let res = match (req.method(), req.uri().path()) {
+        (&Method::GET, "/metrics") => Response::builder()
+            .header("Content-Type", prometheus::TEXT_FORMAT)
+            .body(Body::from(cprices::metrics::gather())),
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty()),
+    };
+    Ok(res.expect("response"))
+}
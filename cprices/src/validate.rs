@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+
+use crate::data::{KLine, Rejected};
+use crate::interval::Interval;
+
+/// Checks OHLCV values of a kline.
+/// Close time is not checked if the interval is unknown
+pub fn check(line: &KLine, interval: Option<Interval>) -> Result<(), (&'static str, String)> {
+    let prices = [
+        line.open_price,
+        line.high_price,
+        line.low_price,
+        line.close_price,
+    ];
+    if prices.iter().any(|p| !p.is_finite() || *p <= 0.0) {
+        return Err((
+            "price",
+            format!(
+                "non positive price: open {}, high {}, low {}, close {}",
+                line.open_price, line.high_price, line.low_price, line.close_price
+            ),
+        ));
+    }
+    if line.high_price < line.open_price.max(line.close_price) {
+        return Err((
+            "high",
+            format!(
+                "high {} < max(open {}, close {})",
+                line.high_price, line.open_price, line.close_price
+            ),
+        ));
+    }
+    if line.low_price > line.open_price.min(line.close_price) {
+        return Err((
+            "low",
+            format!(
+                "low {} > min(open {}, close {})",
+                line.low_price, line.open_price, line.close_price
+            ),
+        ));
+    }
+    let volumes = [
+        line.volume,
+        line.quote_volume,
+        line.taker_buy_volume,
+        line.taker_buy_quote_volume,
+    ];
+    if volumes.iter().any(|v| !v.is_finite() || *v < 0.0) || line.trades < 0 {
+        return Err((
+            "volume",
+            format!(
+                "negative volume: volume {}, quote {}, taker buy {}, taker buy quote {}, trades {}",
+                line.volume,
+                line.quote_volume,
+                line.taker_buy_volume,
+                line.taker_buy_quote_volume,
+                line.trades
+            ),
+        ));
+    }
+    if let Some(interval) = interval {
+        // Binance reports the last millisecond of the interval
+        let expected = interval.close_time(line.open_time()).timestamp_millis();
+        if line.close_time != expected {
+            return Err((
+                "close_time",
+                format!(
+                    "close time {} != open time {} + {} - 1ms",
+                    line.close_time, line.open_time, line.interval
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Splits lines to valid and rejected ones
+pub fn split(lines: Vec<KLine>) -> (Vec<KLine>, Vec<Rejected>) {
+    let mut intervals: HashMap<String, Option<Interval>> = HashMap::new();
+    let mut valid = Vec::with_capacity(lines.len());
+    let mut rejected = Vec::new();
+    for line in lines {
+        let interval = *intervals
+            .entry(line.interval.clone())
+            .or_insert_with(|| line.interval.parse().ok());
+        match check(&line, interval) {
+            Ok(()) => valid.push(line),
+            Err((rule, reason)) => rejected.push(Rejected { line, rule, reason }),
+        }
+    }
+    (valid, rejected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::test_kline;
+
+    fn rule(f: impl Fn(&mut KLine)) -> Option<&'static str> {
+        let mut line = test_kline(60_000, "1m");
+        f(&mut line);
+        check(&line, Some("1m".parse().unwrap()))
+            .err()
+            .map(|(rule, _)| rule)
+    }
+
+    #[test]
+    fn checks() {
+        assert_eq!(rule(|_| {}), None);
+        assert_eq!(rule(|l| l.high_price = 1.4), Some("high"));
+        assert_eq!(rule(|l| l.high_price = 0.9), Some("high"));
+        assert_eq!(rule(|l| l.low_price = 1.1), Some("low"));
+        assert_eq!(rule(|l| l.open_price = 0.0), Some("price"));
+        assert_eq!(rule(|l| l.low_price = -1.0), Some("price"));
+        assert_eq!(rule(|l| l.close_price = f64::NAN), Some("price"));
+        assert_eq!(rule(|l| l.volume = -1.0), Some("volume"));
+        assert_eq!(rule(|l| l.trades = -1), Some("volume"));
+        assert_eq!(rule(|l| l.close_time = 120_000), Some("close_time"));
+        assert_eq!(
+            rule(|l| {
+                l.open_price = 2.0;
+                l.close_price = 2.0;
+                l.high_price = 2.0;
+                l.low_price = 2.0;
+                l.volume = 0.0;
+            }),
+            None
+        );
+    }
+
+    #[test]
+    fn skips_close_time_without_step() {
+        let mut line = test_kline(60_000, "1m");
+        line.close_time = 0;
+        assert!(check(&line, None).is_ok());
+    }
+
+    #[test]
+    fn splits() {
+        let mut bad = test_kline(60_000, "1m");
+        bad.low_price = 1.2;
+        let mut other = test_kline(60_000, "1m");
+        other.open_time = 120_000;
+        other.close_time = 179_999;
+        let (valid, rejected) = split(vec![test_kline(60_000, "1m"), bad, other]);
+        assert_eq!(valid.len(), 2);
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0].rule, "low");
+        assert_eq!(rejected[0].line.low_price, 1.2);
+    }
+}
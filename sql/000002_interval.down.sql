@@ -0,0 +1,12 @@
+--drops kline interval, only 1h rows are kept
+
+BEGIN;
+
+DROP INDEX IF EXISTS crypto_prices_pair_interval_time_idx;
+
+DELETE FROM "crypto_prices" WHERE kline_interval <> '1h';
+ALTER TABLE "crypto_prices" DROP CONSTRAINT crypto_prices_pkey;
+ALTER TABLE "crypto_prices" DROP COLUMN kline_interval;
+ALTER TABLE "crypto_prices" ADD PRIMARY KEY (time, currency_pair);
+
+COMMIT;
@@ -0,0 +1,14 @@
+--adds kline interval to crypto_prices so several intervals of a pair can be stored
+
+BEGIN;
+--rows imported before this migration were loaded with the default 1h interval
+ALTER TABLE "crypto_prices" ADD COLUMN kline_interval VARCHAR (5) NOT NULL DEFAULT '1h';
+ALTER TABLE "crypto_prices" ALTER COLUMN kline_interval DROP DEFAULT;
+
+ALTER TABLE "crypto_prices" DROP CONSTRAINT crypto_prices_pkey;
+ALTER TABLE "crypto_prices" ADD PRIMARY KEY (time, currency_pair, kline_interval);
+
+--index for the last imported time lookup
+CREATE INDEX crypto_prices_pair_interval_time_idx ON "crypto_prices" (currency_pair, kline_interval, time DESC);
+
+COMMIT;
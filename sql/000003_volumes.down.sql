@@ -0,0 +1,10 @@
+--drops quote volume, trade count and taker buy volumes
+
+BEGIN;
+
+ALTER TABLE "crypto_prices" DROP COLUMN IF EXISTS volume_quote;
+ALTER TABLE "crypto_prices" DROP COLUMN IF EXISTS trades;
+ALTER TABLE "crypto_prices" DROP COLUMN IF EXISTS taker_buy_volume_crypto;
+ALTER TABLE "crypto_prices" DROP COLUMN IF EXISTS taker_buy_volume_quote;
+
+COMMIT;
@@ -0,0 +1,11 @@
+--adds quote volume, trade count and taker buy volumes to crypto_prices
+
+BEGIN;
+
+ALTER TABLE "crypto_prices" ADD COLUMN volume_quote DOUBLE PRECISION;
+ALTER TABLE "crypto_prices" ADD COLUMN trades BIGINT;
+--taker sell volume = volume - taker buy volume
+ALTER TABLE "crypto_prices" ADD COLUMN taker_buy_volume_crypto DOUBLE PRECISION;
+ALTER TABLE "crypto_prices" ADD COLUMN taker_buy_volume_quote DOUBLE PRECISION;
+
+COMMIT;
@@ -0,0 +1,7 @@
+--drops is_closed
+
+BEGIN;
+
+ALTER TABLE "crypto_prices" DROP COLUMN IF EXISTS is_closed;
+
+COMMIT;
@@ -0,0 +1,13 @@
+--marks finished candles, the open candle is updated till it closes
+
+BEGIN;
+
+ALTER TABLE "crypto_prices" ADD COLUMN is_closed BOOLEAN NOT NULL DEFAULT TRUE;
+ALTER TABLE "crypto_prices" ALTER COLUMN is_closed DROP DEFAULT;
+
+--the last imported row of a series may be a partial snapshot
+UPDATE "crypto_prices" p SET is_closed = FALSE
+FROM (SELECT currency_pair, kline_interval, MAX(time) AS time FROM "crypto_prices" GROUP BY currency_pair, kline_interval) l
+WHERE p.currency_pair = l.currency_pair AND p.kline_interval = l.kline_interval AND p.time = l.time;
+
+COMMIT;
@@ -0,0 +1,7 @@
+--drops crypto_prices_quarantine
+
+BEGIN;
+
+DROP TABLE IF EXISTS "crypto_prices_quarantine";
+
+COMMIT;
@@ -0,0 +1,27 @@
+--klines failed the sanity checks are kept here instead of crypto_prices
+
+BEGIN;
+
+CREATE TABLE "crypto_prices_quarantine"(
+    time                    TIMESTAMP WITH TIME ZONE NOT NULL,
+    currency_pair           VARCHAR (10) NOT NULL,
+    kline_interval          VARCHAR (5) NOT NULL,
+    opening_price           DOUBLE PRECISION,
+    highest_price           DOUBLE PRECISION,
+    lowest_price            DOUBLE PRECISION,
+    closing_price           DOUBLE PRECISION,
+    volume_crypto           DOUBLE PRECISION,
+    volume_quote            DOUBLE PRECISION,
+    trades                  BIGINT,
+    taker_buy_volume_crypto DOUBLE PRECISION,
+    taker_buy_volume_quote  DOUBLE PRECISION,
+    close_time              TIMESTAMP WITH TIME ZONE,
+    is_closed               BOOLEAN NOT NULL,
+    rule                    VARCHAR (20) NOT NULL,
+    reason                  TEXT NOT NULL,
+    --the open candle may be rejected on every poll, keep the last one
+    received                TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    PRIMARY KEY (time, currency_pair, kline_interval)
+);
+
+COMMIT;
@@ -0,0 +1,17 @@
+--drops market, only spot rows are kept
+
+BEGIN;
+
+DELETE FROM "crypto_prices" WHERE market <> 'spot';
+DROP INDEX IF EXISTS crypto_prices_pair_interval_market_time_idx;
+ALTER TABLE "crypto_prices" DROP CONSTRAINT crypto_prices_pkey;
+ALTER TABLE "crypto_prices" DROP COLUMN IF EXISTS market;
+ALTER TABLE "crypto_prices" ADD PRIMARY KEY (time, currency_pair, kline_interval);
+CREATE INDEX crypto_prices_pair_interval_time_idx ON "crypto_prices" (currency_pair, kline_interval, time DESC);
+
+DELETE FROM "crypto_prices_quarantine" WHERE market <> 'spot';
+ALTER TABLE "crypto_prices_quarantine" DROP CONSTRAINT crypto_prices_quarantine_pkey;
+ALTER TABLE "crypto_prices_quarantine" DROP COLUMN IF EXISTS market;
+ALTER TABLE "crypto_prices_quarantine" ADD PRIMARY KEY (time, currency_pair, kline_interval);
+
+COMMIT;
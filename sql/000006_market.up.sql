@@ -0,0 +1,19 @@
+--adds market type so spot and futures series of the same symbol are kept apart
+
+BEGIN;
+--rows imported before this migration were loaded from the spot market
+ALTER TABLE "crypto_prices" ADD COLUMN market VARCHAR (10) NOT NULL DEFAULT 'spot';
+ALTER TABLE "crypto_prices" ALTER COLUMN market DROP DEFAULT;
+
+ALTER TABLE "crypto_prices" DROP CONSTRAINT crypto_prices_pkey;
+ALTER TABLE "crypto_prices" ADD PRIMARY KEY (time, currency_pair, kline_interval, market);
+
+DROP INDEX IF EXISTS crypto_prices_pair_interval_time_idx;
+CREATE INDEX crypto_prices_pair_interval_market_time_idx ON "crypto_prices" (currency_pair, kline_interval, market, time DESC);
+
+ALTER TABLE "crypto_prices_quarantine" ADD COLUMN market VARCHAR (10) NOT NULL DEFAULT 'spot';
+ALTER TABLE "crypto_prices_quarantine" ALTER COLUMN market DROP DEFAULT;
+ALTER TABLE "crypto_prices_quarantine" DROP CONSTRAINT crypto_prices_quarantine_pkey;
+ALTER TABLE "crypto_prices_quarantine" ADD PRIMARY KEY (time, currency_pair, kline_interval, market);
+
+COMMIT;
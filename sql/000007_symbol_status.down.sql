@@ -0,0 +1,7 @@
+--drops crypto_symbol_status
+
+BEGIN;
+
+DROP TABLE IF EXISTS "crypto_symbol_status";
+
+COMMIT;
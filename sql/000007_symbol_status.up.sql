@@ -0,0 +1,13 @@
+--exchange status changes of the imported pairs, e.g. TRADING, BREAK or DELISTED
+
+BEGIN;
+
+CREATE TABLE "crypto_symbol_status"(
+    time                    TIMESTAMP WITH TIME ZONE NOT NULL,
+    currency_pair           VARCHAR (32) NOT NULL,
+    market                  VARCHAR (10) NOT NULL,
+    status                  VARCHAR (20) NOT NULL,
+    PRIMARY KEY (currency_pair, market, time)
+);
+
+COMMIT;
@@ -0,0 +1,49 @@
+--refuses to roll back while pairs longer than 10 chars are stored, delete them first
+
+BEGIN;
+
+DO $$
+BEGIN
+    IF EXISTS (SELECT 1 FROM "crypto_prices" WHERE length(currency_pair) > 10)
+        OR EXISTS (SELECT 1 FROM "crypto_prices_quarantine" WHERE length(currency_pair) > 10) THEN
+        RAISE EXCEPTION 'pairs longer than 10 chars are stored, delete them and run `importer migrate force 8` to roll back';
+    END IF;
+END $$;
+
+CREATE TABLE "crypto_prices_new"(
+    time                    TIMESTAMP WITH TIME ZONE NOT NULL,
+    opening_price           DOUBLE PRECISION,
+    highest_price           DOUBLE PRECISION,
+    lowest_price            DOUBLE PRECISION,
+    closing_price           DOUBLE PRECISION,
+    volume_crypto           DOUBLE PRECISION,
+    currency_pair           VARCHAR (10) NOT NULL,
+    kline_interval          VARCHAR (5) NOT NULL,
+    volume_quote            DOUBLE PRECISION,
+    trades                  BIGINT,
+    taker_buy_volume_crypto DOUBLE PRECISION,
+    taker_buy_volume_quote  DOUBLE PRECISION,
+    is_closed               BOOLEAN NOT NULL,
+    market                  VARCHAR (10) NOT NULL,
+    PRIMARY KEY (time, currency_pair, kline_interval, market)
+);
+SELECT create_hypertable('crypto_prices_new', 'time', 'currency_pair', 2);
+
+INSERT INTO "crypto_prices_new" (time, opening_price, highest_price, lowest_price, closing_price,
+    volume_crypto, currency_pair, kline_interval, volume_quote, trades, taker_buy_volume_crypto,
+    taker_buy_volume_quote, is_closed, market)
+SELECT time, opening_price, highest_price, lowest_price, closing_price,
+    volume_crypto, currency_pair, kline_interval, volume_quote, trades, taker_buy_volume_crypto,
+    taker_buy_volume_quote, is_closed, market
+FROM "crypto_prices";
+
+DROP TABLE "crypto_prices";
+ALTER TABLE "crypto_prices_new" RENAME TO "crypto_prices";
+ALTER INDEX "crypto_prices_new_pkey" RENAME TO "crypto_prices_pkey";
+ALTER INDEX IF EXISTS "crypto_prices_new_time_idx" RENAME TO "crypto_prices_time_idx";
+ALTER INDEX IF EXISTS "crypto_prices_new_currency_pair_time_idx" RENAME TO "crypto_prices_currency_pair_time_idx";
+CREATE INDEX crypto_prices_pair_interval_market_time_idx ON "crypto_prices" (currency_pair, kline_interval, market, time DESC);
+
+ALTER TABLE "crypto_prices_quarantine" ALTER COLUMN currency_pair TYPE VARCHAR (10);
+
+COMMIT;
@@ -0,0 +1,45 @@
+--discovered symbols may be longer than 10 chars, e.g. 1000PEPEUSDT
+--TimescaleDB does not change the type of a partitioning column,
+--so crypto_prices is copied to a new hypertable and swapped
+
+BEGIN;
+
+CREATE TABLE "crypto_prices_new"(
+    time                    TIMESTAMP WITH TIME ZONE NOT NULL,
+    opening_price           DOUBLE PRECISION,
+    highest_price           DOUBLE PRECISION,
+    lowest_price            DOUBLE PRECISION,
+    closing_price           DOUBLE PRECISION,
+    volume_crypto           DOUBLE PRECISION,
+    currency_pair           VARCHAR (32) NOT NULL,
+    kline_interval          VARCHAR (5) NOT NULL,
+    volume_quote            DOUBLE PRECISION,
+    trades                  BIGINT,
+    taker_buy_volume_crypto DOUBLE PRECISION,
+    taker_buy_volume_quote  DOUBLE PRECISION,
+    is_closed               BOOLEAN NOT NULL,
+    market                  VARCHAR (10) NOT NULL,
+    PRIMARY KEY (time, currency_pair, kline_interval, market)
+);
+SELECT create_hypertable('crypto_prices_new', 'time', 'currency_pair', 2);
+
+INSERT INTO "crypto_prices_new" (time, opening_price, highest_price, lowest_price, closing_price,
+    volume_crypto, currency_pair, kline_interval, volume_quote, trades, taker_buy_volume_crypto,
+    taker_buy_volume_quote, is_closed, market)
+SELECT time, opening_price, highest_price, lowest_price, closing_price,
+    volume_crypto, currency_pair, kline_interval, volume_quote, trades, taker_buy_volume_crypto,
+    taker_buy_volume_quote, is_closed, market
+FROM "crypto_prices";
+
+DROP TABLE "crypto_prices";
+ALTER TABLE "crypto_prices_new" RENAME TO "crypto_prices";
+ALTER INDEX "crypto_prices_new_pkey" RENAME TO "crypto_prices_pkey";
+--the default indexes of create_hypertable
+ALTER INDEX IF EXISTS "crypto_prices_new_time_idx" RENAME TO "crypto_prices_time_idx";
+ALTER INDEX IF EXISTS "crypto_prices_new_currency_pair_time_idx" RENAME TO "crypto_prices_currency_pair_time_idx";
+CREATE INDEX crypto_prices_pair_interval_market_time_idx ON "crypto_prices" (currency_pair, kline_interval, market, time DESC);
+
+--a plain table, the type can be changed in place
+ALTER TABLE "crypto_prices_quarantine" ALTER COLUMN currency_pair TYPE VARCHAR (32);
+
+COMMIT;